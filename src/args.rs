@@ -1,8 +1,107 @@
+use crate::net::config::{self, Link};
 use pico_args::{Error, Arguments};
+use std::{
+	fmt,
+	net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+	path::PathBuf,
+	str::FromStr,
+};
+
+pub const DEFAULT_PORT: u16 = 5323;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+	/// Play on someone else's server
+	Client,
+	/// Host a game without playing in it; runs headless
+	Server,
+	/// Host a game and play in it
+	Listen,
+}
+
+impl FromStr for Role {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"client" => Ok(Role::Client),
+			"server" => Ok(Role::Server),
+			"listen" => Ok(Role::Listen),
+			_ => Err(format!("unknown role '{}'; expected client, server or listen", s)),
+		}
+	}
+}
 
 #[derive(Debug)]
 pub struct Config {
-	pub server: Option<String>,
+	pub role: Role,
+	/// Where clients connect to
+	pub server: SocketAddr,
+	/// Where servers bind to
+	pub listen: SocketAddr,
+	/// Network conditions to simulate
+	pub link: Link,
+	/// What clients authenticate with
+	pub token: Redacted,
+	/// What clients would like to be called
+	pub name: String,
+	/// How servers authenticate clients
+	pub auth: AuthMode,
+	/// How many players servers let in at once
+	pub max_players: usize,
+	/// What servers call themselves on the LAN
+	pub server_name: String,
+	/// What remote admin commands must come with; refused without one
+	pub admin_password: Option<Redacted>,
+	/// Where servers keep banned addresses
+	pub bans: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum AuthMode {
+	Open,
+	Secret(Redacted),
+	Allowlist(PathBuf),
+}
+
+/// Why the command line couldn't be used
+#[derive(Debug)]
+pub enum ArgsError {
+	/// Missing or malformed
+	Parse(Error),
+	/// Well formed, but not usable as given
+	Invalid { arg: String, cause: String },
+}
+
+impl ArgsError {
+	fn invalid(arg: &str, cause: &str) -> Self {
+		ArgsError::Invalid { arg: arg.into(), cause: cause.into() }
+	}
+}
+
+impl From<Error> for ArgsError {
+	fn from(e: Error) -> Self {
+		ArgsError::Parse(e)
+	}
+}
+
+impl fmt::Display for ArgsError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ArgsError::Parse(e) => write!(f, "{}", e),
+			ArgsError::Invalid { arg, cause } => write!(f, "{}: {}", arg, cause),
+		}
+	}
+}
+
+/// A string that's kept out of logs
+#[derive(Clone, Default)]
+pub struct Redacted(pub String);
+
+impl fmt::Debug for Redacted {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "<redacted>")
+	}
 }
 
 pub fn parse_args() -> Option<Config> {
@@ -37,17 +136,135 @@ pub fn parse_args() -> Option<Config> {
 	config
 }
 
-fn inner_parse_args(pargs: &mut Arguments) -> Result<Config, Error> {
+fn inner_parse_args(pargs: &mut Arguments) -> Result<Config, ArgsError> {
+	let server: Option<SocketAddr> = pargs.opt_value_from_fn(["-s", "--server"], parse_addr)?;
+	let listen: Option<SocketAddr> = pargs.opt_value_from_fn(["-l", "--listen"], parse_addr)?;
+	let role: Option<Role> = pargs.opt_value_from_str(["-r", "--role"])?;
+
+	// connecting somewhere implies we aren't the one hosting
+	let role = role.unwrap_or(if server.is_some() { Role::Client } else { Role::Listen });
+
+	let listen = listen.unwrap_or(match role {
+		Role::Server => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DEFAULT_PORT),
+		_ => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DEFAULT_PORT),
+	});
+
+	let server = match (role, server) {
+		(Role::Listen, Some(_)) => return Err(ArgsError::invalid(
+			"--server",
+			"can't connect elsewhere with --role listen; use --listen instead",
+		)),
+		(Role::Listen, None) => loopback_of(listen),
+		(_, Some(server)) => server,
+		(_, None) => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DEFAULT_PORT),
+	};
+
+	let link = parse_link(pargs)?;
+
+	let token: Option<String> = pargs.opt_value_from_str(["-t", "--token"])?;
+	let name = pargs.opt_value_from_str(["-n", "--name"])?.unwrap_or_default();
+
+	let secret: Option<String> = pargs.opt_value_from_str("--secret")?;
+	let allowlist: Option<PathBuf> = pargs.opt_value_from_os_str("--allowlist", |s| Ok::<_, String>(s.into()))?;
+	let auth = match (secret, allowlist) {
+		(None, None) => AuthMode::Open,
+		(Some(secret), None) => AuthMode::Secret(Redacted(secret)),
+		(None, Some(path)) => AuthMode::Allowlist(path),
+		(Some(_), Some(_)) =>
+			return Err(ArgsError::invalid("--secret", "can't be combined with --allowlist")),
+	};
+
+	// a listen server's own client needs to get past its own authenticator
+	let token = Redacted(token.unwrap_or_else(|| match (&role, &auth) {
+		(Role::Listen, AuthMode::Secret(secret)) => secret.0.clone(),
+		_ => String::new(),
+	}));
+
+	let max_players = pargs.opt_value_from_str("--max-players")?.unwrap_or(16);
+	let server_name = pargs.opt_value_from_str("--server-name")?
+		.unwrap_or_else(|| app_name().to_string());
+
+	let admin_password = pargs.opt_value_from_str("--admin-password")?.map(Redacted);
+	let bans = pargs.opt_value_from_os_str("--bans", |s| Ok::<_, String>(s.into()))?
+		.unwrap_or_else(|| PathBuf::from("bans.txt"));
+
 	Ok(Config {
-		server: pargs.opt_value_from_str(["-s", "--server"])?,
+		role,
+		server,
+		listen,
+		link,
+		token,
+		name,
+		auth,
+		max_players,
+		server_name,
+		admin_password,
+		bans,
+	})
+}
+
+fn parse_link(pargs: &mut Arguments) -> Result<Link, ArgsError> {
+	let name: Option<String> = pargs.opt_value_from_str("--link")?;
+	let latency: Option<u32> = pargs.opt_value_from_str("--latency")?;
+	let jitter: Option<u32> = pargs.opt_value_from_str("--jitter")?;
+	let loss: Option<f32> = pargs.opt_value_from_str("--loss")?;
+
+	let name = name.unwrap_or("perfect".into());
+	if name == "custom" {
+		let loss = loss.unwrap_or(0.0);
+		if !(0.0..=100.0).contains(&loss) {
+			return Err(ArgsError::invalid("--loss", "must be a percentage between 0 and 100"));
+		}
+		return Ok(config::custom(latency.unwrap_or(0), jitter.unwrap_or(0), loss / 100.0));
+	}
+
+	if latency.is_some() || jitter.is_some() || loss.is_some() {
+		return Err(ArgsError::invalid("--link", "--latency, --jitter and --loss require --link custom"));
+	}
+
+	Link::preset(&name).ok_or_else(|| ArgsError::Invalid {
+		arg: "--link".into(),
+		cause: format!("unknown link '{}'; expected perfect, wifi, avg, poor or custom", name),
 	})
 }
 
+/// Parses `HOST[:PORT]`, resolving host names as needed. IPv6 addresses need
+/// brackets only when a port is given, e.g. `::1` or `[::1]:5323`.
+fn parse_addr(s: &str) -> Result<SocketAddr, String> {
+	if let Ok(addr) = s.parse() {
+		return Ok(addr);
+	}
+	let bare = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(s);
+	if let Ok(ip) = bare.parse::<IpAddr>() {
+		return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+	}
+
+	let with_port = if s.rsplit_once(':').is_none_or(|(_, p)| p.parse::<u16>().is_err()) {
+		format!("{}:{}", s, DEFAULT_PORT)
+	} else {
+		s.to_string()
+	};
+
+	with_port.to_socket_addrs()
+		.map_err(|e| format!("{}: {}", s, e))?
+		.next()
+		.ok_or_else(|| format!("{}: no addresses found", s))
+}
+
+/// Address a local client can reach a server bound to `addr` at
+fn loopback_of(addr: SocketAddr) -> SocketAddr {
+	if addr.ip().is_unspecified() {
+		SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port())
+	} else {
+		addr
+	}
+}
+
 fn app_name() -> &'static str {
 	env!("CARGO_PKG_NAME")
 }
 
-fn app_version() -> &'static str {
+pub fn app_version() -> &'static str {
 	env!("CARGO_PKG_VERSION")
 }
 
@@ -62,7 +279,29 @@ FLAGS:
   -v, --version       print version information
 
 OPTIONS:
-  -s, --server ADDR   connect to the given server address
+  -r, --role ROLE     client, server (headless) or listen (default: client
+                      with --server, listen otherwise)
+  -s, --server ADDR   connect to the server at HOST[:PORT]
+  -l, --listen ADDR   bind the server to ADDR[:PORT] (default: 127.0.0.1:5323,
+                      or 0.0.0.0:5323 with --role server)
+  -n, --name NAME     name to play as
+  -t, --token TOKEN   token or password to authenticate with
+      --secret SECRET only admit clients whose token is SECRET
+      --allowlist FILE
+                      only admit clients whose token is listed in FILE, as
+                      lines of TOKEN NAME
+      --max-players N turn clients away once N are playing (default: 16)
+      --server-name NAME
+                      name to show on the LAN, where servers not bound to
+                      loopback answer queries on port 5324 (default: shooter)
+      --admin-password PASSWORD
+                      accept remote admin commands sent with PASSWORD
+      --bans FILE     keep banned addresses in FILE (default: bans.txt)
+      --link LINK     simulate perfect, wifi, avg, poor or custom network
+                      conditions (default: perfect)
+      --latency MS    added latency of a custom link
+      --jitter MS     random latency of up to +/- MS on a custom link
+      --loss PCT      percentage of packets dropped on a custom link
 ";
 
 fn print_opts() {
@@ -86,3 +325,23 @@ fn print_try_help() {
 fn print_version() {
 	println!("{} {}", app_name(), app_version());
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_addrs() {
+		let v4 = |a, b, c, d, port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), port);
+		let v6 = |s: &str, port| SocketAddr::new(s.parse().unwrap(), port);
+
+		assert_eq!(parse_addr("10.0.0.1"), Ok(v4(10, 0, 0, 1, DEFAULT_PORT)));
+		assert_eq!(parse_addr("10.0.0.1:1234"), Ok(v4(10, 0, 0, 1, 1234)));
+		assert_eq!(parse_addr("::1"), Ok(v6("::1", DEFAULT_PORT)));
+		assert_eq!(parse_addr("fe80::1:2"), Ok(v6("fe80::1:2", DEFAULT_PORT)));
+		assert_eq!(parse_addr("[::1]"), Ok(v6("::1", DEFAULT_PORT)));
+		assert_eq!(parse_addr("[::1]:1234"), Ok(v6("::1", 1234)));
+		assert_eq!(parse_addr("localhost:1234").map(|a| a.port()), Ok(1234));
+		assert!(parse_addr("no such host.invalid").is_err());
+	}
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use std::time::Duration;
+use crate::{
+	input::interpret::PlayerInput,
+	net::chat::{ChatLog, MAX_CHAT_LEN, SendChat},
+};
+
+// how long lines stay up after they arrive, while the chat box is closed
+const LINE_LIFETIME: Duration = Duration::from_secs(10);
+const VISIBLE_LINES: usize = 8;
+
+/// Shows recent chat, and takes over the keyboard to type some when opened
+/// with Enter. Tab switches between all and team chat, Escape gives up.
+pub struct ChatBoxPlugin;
+
+impl Plugin for ChatBoxPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<ChatBox>()
+			.add_systems(Startup, spawn_chat_box)
+			.add_systems(Update, (
+				sys_chat_box_input,
+				sys_chat_box_text,
+			).chain());
+	}
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct ChatBox {
+	pub open: bool,
+	pub team_only: bool,
+	pub draft: String,
+}
+
+#[derive(Component)]
+struct ChatBoxText;
+
+fn spawn_chat_box(mut cmds: Commands) {
+	let style = TextStyle {
+		font_size: 16.0,
+		color: Color::WHITE,
+		..default()
+	};
+
+	cmds.spawn((
+		ChatBoxText,
+		TextBundle::from_section("", style)
+			.with_style(Style {
+				position_type: PositionType::Absolute,
+				bottom: Val::Px(8.0),
+				left: Val::Px(8.0),
+				max_width: Val::Percent(50.0),
+				..default()
+			}),
+	));
+}
+
+fn sys_chat_box_input(
+	keys: Res<Input<KeyCode>>,
+	mut chars: EventReader<ReceivedCharacter>,
+	mut chat: ResMut<ChatBox>,
+	mut input: ResMut<PlayerInput>,
+	mut sends: EventWriter<SendChat>,
+) {
+	if !chat.open {
+		chars.clear();
+		if keys.just_pressed(KeyCode::Return) {
+			chat.open = true;
+			chat.team_only = false;
+		}
+	} else if keys.just_pressed(KeyCode::Escape) {
+		chat.open = false;
+		chat.draft.clear();
+	} else if keys.just_pressed(KeyCode::Return) {
+		let text = std::mem::take(&mut chat.draft);
+		if !text.trim().is_empty() {
+			sends.send(SendChat { text, team_only: chat.team_only });
+		}
+		chat.open = false;
+	} else {
+		if keys.just_pressed(KeyCode::Tab) {
+			chat.team_only = !chat.team_only;
+		}
+		if keys.just_pressed(KeyCode::Back) {
+			chat.draft.pop();
+		}
+		for ev in chars.read() {
+			if !ev.char.is_control() && chat.draft.chars().count() < MAX_CHAT_LEN {
+				chat.draft.push(ev.char);
+			}
+		}
+	}
+
+	if input.typing != chat.open {
+		input.typing = chat.open;
+	}
+}
+
+fn sys_chat_box_text(
+	chat: Res<ChatBox>,
+	log: Res<ChatLog>,
+	mut q_text: Query<&mut Text, With<ChatBoxText>>,
+) {
+	let mut lines: Vec<String> = log.lines.iter()
+		.rev()
+		.take(VISIBLE_LINES)
+		.filter(|line| chat.open || line.at.elapsed() < LINE_LIFETIME)
+		.map(|line| format!("{}{}: {}", if line.team_only { "(team) " } else { "" }, line.name, line.text))
+		.collect();
+	lines.reverse();
+
+	if chat.open {
+		let to = if chat.team_only { "team" } else { "all" };
+		lines.push(format!("[{}] > {}_", to, chat.draft));
+	}
+
+	let value = lines.join("\n");
+	for mut text in &mut q_text {
+		if text.sections[0].value != value {
+			text.sections[0].value = value.clone();
+		}
+	}
+}
@@ -3,24 +3,32 @@ use bevy::{
 	prelude::*,
 };
 use bevy_prototype_lyon::{
-	shapes::{Circle, RectangleOrigin, Rectangle},
+	shapes::{Circle, Line, Polygon},
 	prelude::{GeometryBuilder, Stroke, Path, ShapeBundle},
 };
 use crate::{debug::Debug, movement::Velocity};
 use crate::layer::Layer;
 use crate::movement::Position;
 use parry2d::{
-	math::{Real, Isometry},
+	math::{Point, Real, Isometry},
 	partitioning::{Qbvh, IndexedData},
 	query::{
 		DefaultQueryDispatcher,
 		QueryDispatcher, details::TOICompositeShapeShapeBestFirstVisitor, TOIStatus,
 	},
-	shape::{SharedShape, Shape, TypedShape, TypedSimdCompositeShape},
+	shape::{SharedShape, Shape, TriMesh, TypedShape, TypedSimdCompositeShape},
+	transformation::hertel_mehlhorn,
 	utils::DefaultStorage,
 };
+use std::{
+	collections::HashMap,
+	f32::consts::PI,
+};
+
+// segments per half circle when outlining capsule ends
+const CAP_SEGMENTS: usize = 8;
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Collidable {
 	pub shape: SharedShape,
 }
@@ -33,6 +41,87 @@ impl Collidable {
 	pub fn aa_rect(w: f32, h: f32) -> Self {
 		SharedShape::cuboid(w / 2.0, h / 2.0).into()
 	}
+
+	/// The convex hull of `points`, in any order; `None` if they're all in a line
+	pub fn convex_polygon(points: &[Vec2]) -> Option<Self> {
+		let points: Vec<_> = points.iter().map(|p| to_point(*p)).collect();
+		SharedShape::convex_hull(&points).map(Into::into)
+	}
+
+	/// Everything within `r` of the segment from `a` to `b`
+	pub fn capsule(a: Vec2, b: Vec2, r: f32) -> Self {
+		SharedShape::capsule(to_point(a), to_point(b), r).into()
+	}
+
+	/// A line from `a` to `b`, with no thickness of its own
+	pub fn segment(a: Vec2, b: Vec2) -> Self {
+		SharedShape::segment(to_point(a), to_point(b)).into()
+	}
+
+	/// Several convex shapes posed relative to the whole, collided with as one.
+	/// Panics if `parts` is empty or holds compounds of its own.
+	pub fn compound(parts: impl IntoIterator<Item = (Position, Collidable)>) -> Self {
+		let parts = parts.into_iter()
+			.map(|(pos, col)| (pos.to_iso(), col.shape))
+			.collect();
+		SharedShape::compound(parts).into()
+	}
+
+	/// The inside of a simple polygon, which needn't be convex, split up into as
+	/// few convex parts as readily found. `None` if `outline` crosses itself.
+	pub fn concave_polygon(outline: &[Vec2]) -> Option<Self> {
+		let edges = || outline.iter().zip(outline.iter().cycle().skip(1)).map(|(a, b)| (*a, *b));
+		let area: f32 = edges().map(|(a, b)| a.perp_dot(b)).sum();
+		if outline.len() < 3 || area.abs() <= f32::EPSILON || crosses_itself(edges) {
+			return None;
+		}
+
+		let mut points: Vec<_> = outline.iter().map(|p| to_point(*p)).collect();
+		// the triangulation expects the outline counter-clockwise
+		if area < 0.0 {
+			points.reverse();
+		}
+
+		let mesh = TriMesh::from_polygon(points)?;
+		let parts = hertel_mehlhorn(mesh.vertices(), mesh.indices()).into_iter()
+			.map(|part| SharedShape::convex_hull(&part).map(|shape| (Isometry::identity(), shape)))
+			.collect::<Option<Vec<_>>>()?;
+		Some(SharedShape::compound(parts).into())
+	}
+}
+
+/// Whether any two edges of a closed outline, other than neighbors, meet
+fn crosses_itself<I: Iterator<Item = (Vec2, Vec2)>>(edges: impl Fn() -> I) -> bool {
+	let n = edges().count();
+	edges().enumerate().any(|(i, (a, b))| {
+		edges().enumerate()
+			.skip(i + 2)
+			// the last edge meets the first at the outline's start
+			.filter(|(j, _)| !(i == 0 && *j == n - 1))
+			.any(|(_, (c, d))| segments_cross(a, b, c, d))
+	})
+}
+
+fn segments_cross(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
+	let side = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+	let (d1, d2) = (side(c, d, a), side(c, d, b));
+	let (d3, d4) = (side(a, b, c), side(a, b, d));
+
+	// all on one line; only a problem if they overlap along it
+	if d3 == 0.0 && d4 == 0.0 {
+		let along = |p: Vec2| (p - a).dot(b - a);
+		let (t0, t1) = (along(c), along(d));
+		return t0.min(t1) <= (b - a).length_squared() && t0.max(t1) >= 0.0;
+	}
+	d1 * d2 <= 0.0 && d3 * d4 <= 0.0
+}
+
+fn to_point(v: Vec2) -> Point<Real> {
+	Point::new(v.x, v.y)
+}
+
+fn to_vec2(p: &Point<Real>) -> Vec2 {
+	Vec2::new(p.x, p.y)
 }
 
 impl From<SharedShape> for Collidable {
@@ -94,23 +183,56 @@ impl<'a, 'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery> TypedSimdCompositeShape f
 	}
 
 	fn typed_qbvh(&self) -> &Qbvh<EntityHandle> {
-		&self.bvh
+		self.bvh
 	}
 }
 
 fn shape_to_path(shape: &dyn Shape) -> Path {
-	let builder = GeometryBuilder::new();
+	add_outline(GeometryBuilder::new(), shape, &Isometry::identity()).build()
+}
+
+/// Adds the outline of `shape`, posed by `iso`
+fn add_outline(builder: GeometryBuilder, shape: &dyn Shape, iso: &Isometry<Real>) -> GeometryBuilder {
+	let at = |p: &Point<Real>| to_vec2(&(iso * p));
+
 	match shape.as_typed_shape() {
 		TypedShape::Ball(b) => builder.add(&Circle {
-			center: Vec2::ZERO,
+			center: at(&Point::origin()),
 			radius:b.radius,
 		}),
-		TypedShape::Cuboid(c) => builder.add(&Rectangle {
-			extents: 2.0 * Vec2::new(c.half_extents.x, c.half_extents.y),
-			origin: RectangleOrigin::Center,
+		TypedShape::Cuboid(c) => {
+			let h = c.half_extents;
+			let corners = [(-h.x, -h.y), (h.x, -h.y), (h.x, h.y), (-h.x, h.y)];
+			builder.add(&Polygon {
+				points: corners.iter().map(|(x, y)| at(&Point::new(*x, *y))).collect(),
+				closed: true,
+			})
+		},
+		TypedShape::ConvexPolygon(p) => builder.add(&Polygon {
+			points: p.points().iter().map(at).collect(),
+			closed: true,
+		}),
+		TypedShape::Capsule(c) => builder.add(&Polygon {
+			points: capsule_outline(at(&c.segment.a), at(&c.segment.b), c.radius),
+			closed: true,
 		}),
+		TypedShape::Segment(s) => builder.add(&Line(at(&s.a), at(&s.b))),
+		TypedShape::Compound(c) => c.shapes().iter()
+			.fold(builder, |builder, (part_iso, part)| add_outline(builder, part.as_ref(), &(iso * part_iso))),
 		_ => panic!("Unimplemented shape type {:?}", shape.shape_type()),
-	}.build()
+	}
+}
+
+/// Half circles around `b` then `a`, joined into one loop
+fn capsule_outline(a: Vec2, b: Vec2, r: f32) -> Vec<Vec2> {
+	let along = (b - a).try_normalize().unwrap_or(Vec2::Y);
+	let right = -along.perp();
+	let start = right.y.atan2(right.x);
+
+	let half_circle = |center: Vec2, from: f32| (0..=CAP_SEGMENTS).map(move |i| {
+		center + r * Vec2::from_angle(from + PI * i as f32 / CAP_SEGMENTS as f32)
+	});
+	half_circle(b, start).chain(half_circle(a, start + PI)).collect()
 }
 
 #[derive(Component, Default, Reflect)]
@@ -120,17 +242,18 @@ pub struct CollidableDebug;
 pub fn sys_collide_debug_add(
 	mut cmds: Commands,
 	debug: Res<Debug>,
-	q_added: Query<(Entity, &Collidable, &Transform), Added<Collidable>>
+	q_added: Query<(Entity, &Collidable, &Position, &Transform), Added<Collidable>>
 ) {
-	for (ent, col, t) in &q_added {
+	for (ent, col, pos, t) in &q_added {
 		let id = cmds.spawn((
 				CollidableDebug,
 				Name::new("CollidableDebug"),
 				ShapeBundle {
 					path: shape_to_path(col.shape.as_ref()),
 					spatial: SpatialBundle {
+						// undo however the parent is drawn, to show the pose collided with
 						transform: Transform::from_xyz(0.0, 0.0, Layer::FG)
-							.with_rotation(-t.rotation),
+							.with_rotation(t.rotation.inverse() * pos.to_quat()),
 						visibility: if debug.enabled { Visibility::Inherited } else { Visibility::Hidden },
 						..default()
 					},
@@ -174,11 +297,18 @@ impl Contact {
 /// Results are from the perspective of `col1`
 pub fn contact(
 	col1: &Collidable, pos1: &Position, col2: &Collidable, pos2: &Position
+) -> Option<Contact> {
+	contact_iso(col1.shape.as_ref(), &pos1.to_iso(), col2.shape.as_ref(), &pos2.to_iso())
+}
+
+/// Results are from the perspective of `shape1`
+fn contact_iso(
+	shape1: &dyn Shape, iso1: &Isometry<Real>, shape2: &dyn Shape, iso2: &Isometry<Real>
 ) -> Option<Contact> {
 	let res = DefaultQueryDispatcher{}.contact(
-		&(pos2 - pos1).to_iso(),
-		col1.shape.as_ref(),
-		col2.shape.as_ref(),
+		&iso1.inv_mul(iso2),
+		shape1,
+		shape2,
 		f32::MAX,
 	);
 
@@ -191,15 +321,19 @@ pub fn contact(
 		},
 	};
 
+	let pos = iso1 * contact.point1;
+	let norm = iso2 * contact.normal2;
 	Some(Contact {
-		pos: Vec2::new(contact.point1.x, contact.point1.y) + pos1.p,
-		norm: Vec2::new(contact.normal2.x, contact.normal2.y),
+		pos: Vec2::new(pos.x, pos.y),
+		norm: Vec2::new(norm.x, norm.y),
 		dist: -contact.dist,
 	})
 }
 
 #[derive(Debug)]
 pub struct Toi {
+	/// Where the first contact is, on whatever was hit
+	pub pos: Vec2,
 	pub norm: Vec2,
 	pub toi_sec: f32,
 }
@@ -219,11 +353,27 @@ pub fn toi<'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery>(
 	vel: &Velocity,
 	max_toi_sec: f32
 ) -> ToiResult {
-	let dispatcher = DefaultQueryDispatcher{};
 	let shapes = QueryCompositeShape {
-		query: &query_geometry,
-		bvh: &query_bvh,
+		query: query_geometry,
+		bvh: query_bvh,
 	};
+
+	toi_hit(&shapes, col, pos, vel, max_toi_sec)
+		.map_or(ToiResult::Miss, |(_, res)| res)
+}
+
+/// Like `toi`, but against any set of shapes, and also reports what was hit
+pub fn toi_hit<S>(
+	shapes: &S,
+	col: &Collidable,
+	pos: &Position,
+	vel: &Velocity,
+	max_toi_sec: f32
+) -> Option<(Entity, ToiResult)>
+where
+	S: TypedSimdCompositeShape<PartShape = dyn Shape, PartId = EntityHandle, QbvhStorage = DefaultStorage>,
+{
+	let dispatcher = DefaultQueryDispatcher{};
 	let pos_iso = pos.to_iso();
 	let vel_v2 = vel.to_vector2();
 
@@ -231,25 +381,246 @@ pub fn toi<'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery>(
 		&dispatcher,
 		&pos_iso,
 		&vel_v2,
-		&shapes,
+		shapes,
 		col.shape.as_ref(),
 		max_toi_sec,
 		true,
 	);
 
-	let (ent, toi) = unwrap!(query_bvh.traverse_best_first(&mut visitor).map(|h| h.1), {
-		return ToiResult::Miss;
-	});
+	let (ent, toi) = shapes.typed_qbvh().traverse_best_first(&mut visitor).map(|h| h.1)?;
 
 	if toi.status == TOIStatus::Converged && toi.toi > 0.0 {
-		return ToiResult::Toi(Toi {
+		return Some((ent.0, ToiResult::Toi(Toi {
+			pos: to_vec2(&toi.witness1),
 			norm: Vec2::new(toi.normal1.x, toi.normal1.y),
 			toi_sec: toi.toi,
-		});
+		})));
+	}
+
+	let mut res = None;
+	shapes.map_typed_part_at(ent, |geo_iso, geo_shape| {
+		let geo_iso = geo_iso.copied().unwrap_or_else(Isometry::identity);
+		res = contact_iso(col.shape.as_ref(), &pos_iso, geo_shape, &geo_iso);
+	});
+
+	// the sweep gave up without converging; only an overlap is worth reporting,
+	// since a gap would be a wall that's never actually reached
+	res.filter(|c| c.dist > 0.0).map(|c| (ent.0, ToiResult::Contact(c)))
+}
+
+/// Shapes posed independently of the world, e.g. from a history buffer
+#[derive(Default)]
+pub struct PosedShapes {
+	parts: HashMap<Entity, (SharedShape, Isometry<Real>)>,
+	bvh: Qbvh<EntityHandle>,
+}
+
+impl PosedShapes {
+	/// The tree is built in the order `parts` come in, so the same parts in the
+	/// same order sweep the same on every machine
+	pub fn new(parts: impl IntoIterator<Item = (Entity, SharedShape, Position)>) -> Self {
+		let parts: Vec<_> = parts.into_iter()
+			.map(|(ent, shape, pos)| (ent, (shape, pos.to_iso())))
+			.collect();
+
+		let mut bvh = Qbvh::new();
+		let aabbs = parts.iter()
+			.map(|(ent, (shape, iso))| (EntityHandle::from(*ent), shape.compute_aabb(iso)));
+		bvh.clear_and_rebuild(aabbs, 0.0);
+
+		Self { parts: parts.into_iter().collect(), bvh }
+	}
+
+	/// These same shapes, less `ent`'s, e.g. to sweep a player past everyone else
+	pub fn excluding(&self, ent: Entity) -> Excluding<'_> {
+		Excluding { shapes: self, ent }
+	}
+}
+
+/// `PosedShapes` with one left out, without building another tree
+pub struct Excluding<'a> {
+	shapes: &'a PosedShapes,
+	ent: Entity,
+}
+
+impl TypedSimdCompositeShape for Excluding<'_> {
+	type PartShape = dyn Shape;
+	type PartId = EntityHandle;
+	type QbvhStorage = DefaultStorage;
+
+	fn map_typed_part_at(
+		&self,
+		shape_id: Self::PartId,
+		f: impl FnMut(Option<&Isometry<Real>>, &Self::PartShape),
+	) {
+		// never visited, so never hit
+		if shape_id.0 != self.ent {
+			self.shapes.map_typed_part_at(shape_id, f);
+		}
+	}
+
+	fn map_untyped_part_at(
+		&self,
+		shape_id: Self::PartId,
+		f: impl FnMut(Option<&Isometry<Real>>, &Self::PartShape),
+	) {
+		self.map_typed_part_at(shape_id, f);
+	}
+
+	fn typed_qbvh(&self) -> &Qbvh<EntityHandle> {
+		self.shapes.typed_qbvh()
 	}
+}
 
-	let geo_col = query_geometry.get_component::<Collidable>(ent.0).unwrap();
-	let geo_pos = query_geometry.get_component::<Position>(ent.0).unwrap();
+impl TypedSimdCompositeShape for PosedShapes {
+	type PartShape = dyn Shape;
+	type PartId = EntityHandle;
+	type QbvhStorage = DefaultStorage;
+
+	fn map_typed_part_at(
+		&self,
+		shape_id: Self::PartId,
+		mut f: impl FnMut(Option<&Isometry<Real>>, &Self::PartShape),
+	) {
+		if let Some((shape, iso)) = self.parts.get(&shape_id.0) {
+			f(Some(iso), shape.as_ref())
+		}
+	}
 
-	return ToiResult::Contact(contact(col, &pos, geo_col, geo_pos).unwrap());
+	fn map_untyped_part_at(
+		&self,
+		shape_id: Self::PartId,
+		f: impl FnMut(Option<&Isometry<Real>>, &Self::PartShape),
+	) {
+		self.map_typed_part_at(shape_id, f);
+	}
+
+	fn typed_qbvh(&self) -> &Qbvh<EntityHandle> {
+		&self.bvh
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const R: f32 = 5.0;
+
+	// sweeps a ball of radius `R` from `from` at `v` for up to 2s against `col`, posed at `pos`
+	fn sweep(col: Collidable, pos: Position, from: Vec2, v: Vec2) -> ToiResult {
+		let ent = Entity::from_raw(1);
+		let shapes = PosedShapes::new([(ent, col.shape, pos)]);
+		let from = Position::from(from);
+		toi_hit(&shapes, &Collidable::circle(R), &from, &v.into(), 2.0)
+			.map_or(ToiResult::Miss, |(hit, res)| {
+				assert_eq!(hit, ent);
+				res
+			})
+	}
+
+	fn assert_toi(res: ToiResult, toi_sec: f32, norm: Vec2) {
+		let toi = match res {
+			ToiResult::Toi(toi) => toi,
+			res => panic!("expected a toi, got {:?}", res),
+		};
+		assert!((toi.toi_sec - toi_sec).abs() < 1e-3, "{:?}", toi);
+		assert!(toi.norm.distance(norm) < 1e-3, "{:?}", toi);
+	}
+
+	fn l_shape() -> Collidable {
+		// the notch is (20, 20) to (60, 60)
+		let outline = [
+			Vec2::new(0.0, 0.0),
+			Vec2::new(60.0, 0.0),
+			Vec2::new(60.0, 20.0),
+			Vec2::new(20.0, 20.0),
+			Vec2::new(20.0, 60.0),
+			Vec2::new(0.0, 60.0),
+		];
+		Collidable::concave_polygon(&outline).unwrap()
+	}
+
+	#[test]
+	fn rotated_capsule() {
+		let capsule = || Collidable::capsule(Vec2::new(-50.0, 0.0), Vec2::new(50.0, 0.0), 10.0);
+		let upright = Position::ZERO.with_rotation(0.25);
+
+		// stood upright, it spans y in [-60, 60] and x in [-10, 10]
+		let res = sweep(capsule(), upright, Vec2::new(-100.0, 30.0), Vec2::new(100.0, 0.0));
+		assert_toi(res, 0.85, Vec2::NEG_X);
+
+		// lying down, it would be passed over
+		let res = sweep(capsule(), Position::ZERO, Vec2::new(-100.0, 30.0), Vec2::new(100.0, 0.0));
+		assert!(matches!(res, ToiResult::Miss), "{:?}", res);
+	}
+
+	#[test]
+	fn segment() {
+		let segment = Collidable::segment(Vec2::new(0.0, -50.0), Vec2::new(0.0, 50.0));
+		let res = sweep(segment, Position::ZERO, Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0));
+		assert_toi(res, 0.95, Vec2::NEG_X);
+	}
+
+	#[test]
+	fn stops_short() {
+		let segment = || Collidable::segment(Vec2::new(0.0, -50.0), Vec2::new(0.0, 50.0));
+
+		// only gets 40 of the 95 it would take to touch
+		let res = sweep(segment(), Position::ZERO, Vec2::new(-100.0, 0.0), Vec2::new(20.0, 0.0));
+		assert!(matches!(res, ToiResult::Miss), "{:?}", res);
+
+		// already clear of it, and moving further away
+		let res = sweep(segment(), Position::ZERO, Vec2::new(-R - 1.0, 0.0), Vec2::new(-100.0, 0.0));
+		assert!(matches!(res, ToiResult::Miss), "{:?}", res);
+
+		// grazing along its end, without ever touching
+		let res = sweep(segment(), Position::ZERO, Vec2::new(-100.0, 50.0 + R + 1.0), Vec2::new(100.0, 0.0));
+		assert!(matches!(res, ToiResult::Miss), "{:?}", res);
+	}
+
+	#[test]
+	fn convex_polygon() {
+		let square = [
+			Vec2::new(-20.0, -20.0),
+			Vec2::new(20.0, -20.0),
+			Vec2::new(20.0, 20.0),
+			Vec2::new(-20.0, 20.0),
+		];
+		let diamond = Position::ZERO.with_rotation(0.125);
+		let col = Collidable::convex_polygon(&square).unwrap();
+
+		// head on into the corner
+		let res = sweep(col, diamond, Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0));
+		let corner = 20.0 * 2f32.sqrt();
+		assert_toi(res, (100.0 - corner - R) / 100.0, Vec2::NEG_X);
+	}
+
+	#[test]
+	fn concave_polygon() {
+		// straight down through the notch, onto the top of the lower arm; its
+		// convex hull would've been hit well before
+		let res = sweep(l_shape(), Position::ZERO, Vec2::new(40.0, 100.0), Vec2::new(0.0, -100.0));
+		assert_toi(res, 0.75, Vec2::Y);
+
+		// from the right, into the inside corner
+		let res = sweep(l_shape(), Position::ZERO, Vec2::new(100.0, 40.0), Vec2::new(-100.0, 0.0));
+		assert_toi(res, 0.75, Vec2::X);
+	}
+
+	#[test]
+	fn concave_polygon_contact() {
+		let ball = Collidable::circle(R);
+
+		// sitting in the notch touches nothing
+		let c = contact(&ball, &Position::new(40.0, 40.0), &l_shape(), &Position::ZERO).unwrap();
+		assert!(c.dist < 0.0, "{:?}", c);
+
+		// sitting in an arm overlaps it
+		let c = contact(&ball, &Position::new(10.0, 40.0), &l_shape(), &Position::ZERO).unwrap();
+		assert!(c.dist > 0.0, "{:?}", c);
+
+		// and sweeping from there reports the overlap, rather than a hit
+		let res = sweep(l_shape(), Position::ZERO, Vec2::new(10.0, 40.0), Vec2::new(100.0, 0.0));
+		assert!(matches!(&res, ToiResult::Contact(c) if c.dist > 0.0), "{:?}", res);
+	}
 }
@@ -32,6 +32,9 @@ pub struct PlayerInput {
 	pub primary: bool,
 	pub debug: bool,
 	pub full_screen: bool,
+	pub net_graph: bool,
+	/// Keys are spelling out a chat message, rather than steering
+	pub typing: bool,
 }
 
 pub fn sys_input_type	(
@@ -64,6 +67,7 @@ pub fn sys_input_type	(
 	}
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn sys_player_input(
 	mut input: ResMut<PlayerInput>,
 	keyboard: Res<Keyboard>,
@@ -106,6 +110,11 @@ fn keyboard_input(
 	}
 	input.dir = dir.normalize_or_zero();
 
+	// those keys are spelling out a chat message instead
+	if input.typing {
+		input.dir = Vec2::ZERO;
+	}
+
 	// face
 
 	let (cam, cam_t) = q_camera.single();
@@ -127,6 +136,10 @@ fn keyboard_input(
 		input.full_screen = !input.full_screen;
 	}
 
+	if keys.just_released(KeyCode::F10) {
+		input.net_graph = !input.net_graph;
+	}
+
 	if keys.just_released(KeyCode::F12) {
 		input.debug = !input.debug;
 	}
@@ -183,5 +196,8 @@ fn gamepad_input(
 		if gamepad.buttons.just_pressed(GamepadButton::new(id, GamepadButtonType::West)) {
 			input.debug = !input.debug;
 		}
+		if gamepad.buttons.just_pressed(GamepadButton::new(id, GamepadButtonType::Select)) {
+			input.net_graph = !input.net_graph;
+		}
 	}
 }
@@ -5,6 +5,7 @@ mod macros;
 
 mod animation;
 mod args;
+mod chat_box;
 mod collide;
 mod debug;
 mod input;
@@ -13,6 +14,8 @@ mod metric;
 mod movement;
 mod player;
 mod net;
+mod net_graph;
+mod sim;
 mod tick_schedule;
 mod time;
 
@@ -21,21 +24,19 @@ use animation::{
 	AnimationTimer,
 	sys_animate_sprite,
 };
-use args::parse_args;
+use args::{AuthMode, parse_args, Role};
 use bevy::{
+	log::LogPlugin,
 	prelude::*,
 	utils::{Duration, HashMap},
 	window::PrimaryWindow,
 };
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_prototype_lyon::plugin::ShapePlugin;
+use chat_box::ChatBoxPlugin;
 use collide::{
-	Collidable,
-	EntityHandle,
 	sys_collide_debug_add,
 	sys_collide_debug_toggle,
-	toi,
-	ToiResult,
 };
 use debug::{debug_enabled, Debug};
 use input::{
@@ -53,12 +54,27 @@ use input::{
 use layer::Layer;
 use metric::Metric;
 use movement::{Position, sys_write_back, Velocity};
-use net::{client::NetClientPlugin, server::NetServerPlugin};
-use parry2d::partitioning::Qbvh;
-use player::Player;
-use std::thread;
+use net::{
+	admin::{AdminConsole, AdminPassword, Bans},
+	discovery::{LanScanPlugin, ServerInfo},
+	auth::{Allowlist, Authenticator, Open, ServerAuth, SharedSecret},
+	client::{ConnectionState, Credentials, NetClientPlugin, Rejected, ServerAddr},
+	clock::ClockSync,
+	config::{Link, TICK_INTERVAL},
+	predict::Predicted,
+	server::{ListenAddr, NetServerPlugin, ShutdownSignal},
+	session::Sessions,
+};
+use net_graph::{NetGraph, NetGraphPlugin};
+use player::{Intent, LocalPlayer, Player, PlayerId, Team};
+use sim::{player_bundle, Shot, SimPlugin, Skin, systems_tick};
+use std::{
+	net::SocketAddr,
+	path::Path,
+	sync::atomic::Ordering,
+	thread,
+};
 use tick_schedule::{TickConfig, TickPlugin, TickSchedule};
-use time::Accumulator;
 
 const TURN_RADS: f32 = std::f32::consts::TAU;
 const TURN_2_RADS: f32 = std::f32::consts::PI;
@@ -71,12 +87,26 @@ fn main() {
 
 	println!("{:?}", config);
 
-	if config.server == None {
-		thread::spawn(move || {
-			App::new()
-				.add_plugins(NetServerPlugin)
-				.run();
-		});
+	let listen = config.listen;
+	let link = config.link.clone();
+	let sessions = Sessions::new(config.max_players);
+	let password = AdminPassword(config.admin_password.map(|p| p.0));
+	let info = ServerInfo { name: config.server_name, ..default() };
+	match config.role {
+		Role::Client => {},
+		Role::Server => {
+			let auth = unwrap!(server_auth(&config.auth), { return; });
+			let bans = unwrap!(load_bans(&config.bans), { return; });
+			return run_headless_server(listen, link, auth, sessions, password, bans, info);
+		},
+		Role::Listen => {
+			let auth = unwrap!(server_auth(&config.auth), { return; });
+			let bans = unwrap!(load_bans(&config.bans), { return; });
+			thread::spawn(move || {
+				let mut app = server_app(listen, link, auth, sessions, password, bans, info);
+				app.insert_resource(AdminConsole::stdin()).run();
+			});
+		},
 	}
 
 	let mut app = App::new();
@@ -84,32 +114,37 @@ fn main() {
 	app
 		// types
 		.register_tick_input()
-		.register_type::<Accumulator>()
-		.register_type::<Player>()
+		.register_type::<LocalPlayer>()
 		.register_type::<PlayerInput>()
-		.register_type::<Position>()
-		.register_type::<Shot>()
-		.register_type::<Velocity>()
 
 		// resources
 		.init_tick_input() // must come before DefaultPlugins
 		.insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.2)))
 		.insert_resource(Debug::default())
 		.insert_resource(PlayerInput::default())
+		.insert_resource(ServerAddr(config.server))
+		.insert_resource(Credentials {
+			token: config.token.0,
+			name: config.name,
+		})
+		.insert_resource(config.link)
 		.insert_resource(Sounds(HashMap::new()))
-		.insert_resource(Statics(Qbvh::new()))
 		.insert_resource(Textures(HashMap::new()))
 		.insert_resource(TickConfig {
 			budget: Duration::from_millis(100),
-			interval: Duration::from_nanos(1_000_000_000 / 60),
+			interval: TICK_INTERVAL,
+			step: TICK_INTERVAL,
 		})
 
 		// plugins
 		.add_plugins((
 			TickPlugin,
 			DefaultPlugins,
-			// TODO -- send endpoint config
+			SimPlugin,
+			ChatBoxPlugin,
+			LanScanPlugin,
 			NetClientPlugin,
+			NetGraphPlugin,
 			ShapePlugin,
 			WorldInspectorPlugin::default()
 				.run_if(debug_enabled),
@@ -123,33 +158,36 @@ fn main() {
 			(
 				spawn_bg,
 				spawn_player,
-				spawn_statics,
 				sys_spawn_shots,
 			).after(load_assets),
 		))
-		.add_systems(PostStartup,
-			sys_index_statics
-		)
 		.add_systems(Update, (
 			sys_animate_sprite,
-			sys_collide_debug_add,
+			(
+				sys_skin_add,
+				sys_player_animation_add,
+				sys_shot_sound_add,
+				sys_collide_debug_add,
+			).chain(),
 			sys_collide_debug_toggle,
+			sys_connection_title,
 		))
-		.add_systems(TickSchedule::InputCollect, systems_tick_input_collect())
-		.add_systems(Last, systems_input_gc())
-		.add_systems(TickSchedule::Tick, (
-			sys_tps,
+		.add_systems(TickSchedule::InputCollect, (
+			systems_tick_input_collect(),
 			(
 				sys_input_type,
 				sys_player_input,
 				sys_apply_input,
-				sys_spawn_shot,
-				sys_move_shots,
-				sys_move_player,
 			).chain(),
+		).chain())
+		.add_systems(Last, systems_input_gc())
+		.add_systems(TickSchedule::Tick, (
+			sys_tps,
+			systems_tick(),
 		))
 		.add_systems(TickSchedule::PostTicks, (
 			sys_fps,
+			sys_player_animation,
 			sys_write_back,
 			update_camera,
 		))
@@ -158,19 +196,113 @@ fn main() {
 	app.run();
 }
 
+/// A server app, sans any logging, signal handling, etc. that belongs to the process
+fn server_app(
+	listen: SocketAddr,
+	link: Link,
+	auth: ServerAuth,
+	sessions: Sessions,
+	password: AdminPassword,
+	bans: Bans,
+	info: ServerInfo,
+) -> App {
+	let mut app = App::new();
+	app
+		.insert_resource(ListenAddr(listen))
+		.insert_resource(auth)
+		.insert_resource(sessions)
+		.insert_resource(password)
+		.insert_resource(bans)
+		.insert_resource(info)
+		.insert_resource(link)
+		.add_plugins((MinimalPlugins, SimPlugin, NetServerPlugin));
+
+	app
+}
+
+fn server_auth(mode: &AuthMode) -> Option<ServerAuth> {
+	let auth: Box<dyn Authenticator> = match mode {
+		AuthMode::Open => Box::new(Open),
+		AuthMode::Secret(secret) => Box::new(SharedSecret(secret.0.clone())),
+		AuthMode::Allowlist(path) => match Allowlist::load(path) {
+			Ok(list) => Box::new(list),
+			Err(e) => {
+				eprintln!("Failed to load allowlist {}: {}", path.display(), e);
+				return None;
+			},
+		},
+	};
+
+	Some(ServerAuth(auth))
+}
+
+fn load_bans(path: &Path) -> Option<Bans> {
+	match Bans::load(path) {
+		Ok(bans) => Some(bans),
+		Err(e) => {
+			eprintln!("Failed to load bans {}: {}", path.display(), e);
+			None
+		},
+	}
+}
+
+/// Runs a dedicated server in the foreground, until interrupted
+fn run_headless_server(
+	listen: SocketAddr,
+	link: Link,
+	auth: ServerAuth,
+	sessions: Sessions,
+	password: AdminPassword,
+	bans: Bans,
+	info: ServerInfo,
+) {
+	let signal = ShutdownSignal::default();
+	let flag = signal.0.clone();
+	if let Err(e) = ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed)) {
+		eprintln!("Failed to install signal handler: {}", e);
+		return;
+	}
+
+	let mut app = server_app(listen, link, auth, sessions, password, bans, info);
+	app
+		.insert_resource(signal)
+		.insert_resource(AdminConsole::stdin())
+		.add_plugins(LogPlugin::default())
+		.run();
+}
+
 #[derive(Resource)]
 struct Sounds(HashMap<String, Handle<AudioSource>>);
 
 #[derive(Resource)]
 struct Textures(HashMap<String, Handle<TextureAtlas>>);
 
-#[derive(Resource)]
-struct Statics(Qbvh<EntityHandle>);
-
 fn sys_window_setup(mut window: Query<&mut Window>) {
 	window.single_mut().title = "shooter".into();
 }
 
+fn sys_connection_title(
+	state: Res<ConnectionState>,
+	mut rejections: EventReader<Rejected>,
+	mut rejected: Local<Option<String>>,
+	mut window: Query<&mut Window>,
+) {
+	if let Some(ev) = rejections.read().last() {
+		*rejected = Some(ev.reason.clone());
+	} else if !state.is_changed() {
+		return;
+	}
+
+	let status = match (*state, &*rejected) {
+		(ConnectionState::Connecting, _) => " - connecting...".to_string(),
+		(ConnectionState::Connected, _) => String::new(),
+		(ConnectionState::Reconnecting { .. }, _) => " - reconnecting...".to_string(),
+		(ConnectionState::Closed, Some(reason)) => format!(" - rejected: {}", reason),
+		(ConnectionState::Closed, None) => " - disconnected".to_string(),
+	};
+	window.single_mut().title = format!("shooter{}", status);
+}
+
 fn spawn_camera(mut cmds: Commands) {
 	let scale = 1.5;
 	let far  = 1000.0;
@@ -184,7 +316,7 @@ fn spawn_camera(mut cmds: Commands) {
 
 fn update_camera(
 	mut q_camera: Query<&mut Transform, With<Camera>>,
-	q_player: Query<&Position, (With<Player>, Without<Camera>)>
+	q_player: Query<&Position, (With<LocalPlayer>, Without<Camera>)>
 ) {
 	let pos = q_player.single();
 	let c = &mut q_camera.single_mut().translation;
@@ -192,220 +324,80 @@ fn update_camera(
 	c.y = pos.p.y;
 }
 
-fn sys_index_statics(
-	mut statics: ResMut<Statics>,
-	q_walls: Query<(Entity, &Collidable, &Position), With<Static>>
-) {
-	let statics = &mut statics.0;
-	let shapes = q_walls.iter()
-		.map(|(ent, ref col, ref pos)| (EntityHandle::from(ent), col.shape.compute_aabb(&pos.to_iso())));
-	statics.clear_and_rebuild(shapes, 0.0);
-}
-
-fn reflect(v: Vec2, norm: Vec2) -> Vec2 {
-	v - 2.0 * v.dot(norm) * norm
-}
-
-fn slide(v: Vec2, norm: Vec2) -> Vec2 {
-	v - v.dot(norm) * norm
+fn spawn_player(mut cmds: Commands) {
+	cmds.spawn((
+		player_bundle(PlayerId::default(), "Player", Team::default(), Position::ZERO),
+		LocalPlayer,
+		Predicted,
+	));
 }
 
-fn sys_move_shots(
+fn sys_player_animation_add(
 	mut cmds: Commands,
-	statics: Res<Statics>,
-	tick: Res<TickConfig>,
-	mut q_shots: Query<(Entity, &Collidable, &mut Position, &mut Velocity, &mut Shot)>,
-	q_statics: Query<(Entity, &Collidable, &Position), (With<Static>, Without<Shot>)>,
+	q_added: Query<Entity, Added<Player>>,
 ) {
-	let statics = &statics.0;
-	let step_secs = tick.interval.as_secs_f32();
-
-	for (ent, col, mut pos, mut vel, mut shot) in &mut q_shots {
-
-		//info!("    ----");
-
-		let mut max_toi = step_secs;
-		let mut limit = 8;
-		while max_toi > 0.0 && limit > 0 {
-			limit -= 1;
-
-			//info!("pos: {:?}; v: {:?}; max_toi: {}", pos.p, vel.v, max_toi);
-
-			let margin:f32 = 1024.0 * f32::EPSILON;
-			match toi(&q_statics, &statics, col, &pos, &vel, max_toi) {
-				ToiResult::Miss => {
-					pos.p += vel.v * max_toi;
-					break;
-				},
-				ToiResult::Contact(contact) => {
-					//info!("contact: {:?}", contact);
-
-					pos.p += contact.norm * (contact.dist + margin);
-				},
-				ToiResult::Toi(toi) => {
-					//info!("toi: {:?}", toi);
-
-					if shot.bounces == 0 {
-						cmds.entity(ent)
-							.despawn_recursive();
-						break;
-					}
-
-					shot.bounces -= 1;
-
-					max_toi -= toi.toi_sec;
-					pos.p += vel.v * toi.toi_sec + toi.norm * margin;
-					vel.v = reflect(vel.v, toi.norm);
-				},
-			}
-		}
+	for ent in &q_added {
+		let animation_indicies = AnimationIndices { first: 0, last: 2, direction: 1 };
+		let mut animation_timer = AnimationTimer(Timer::from_seconds(0.2, TimerMode::Repeating));
+		animation_timer.pause();
+		cmds.entity(ent).insert((
+			animation_indicies,
+			animation_timer,
+		));
 	}
 }
 
-fn sys_move_player(
-	statics: Res<Statics>,
-	tick: Res<TickConfig>,
-	mut q_player: Query<(&Collidable, &mut Position, &Velocity), With<Player>>,
-	q_statics: Query<(Entity, &Collidable, &Position), (With<Static>, Without<Player>)>,
+/// Dresses anything with a new skin, or a changed one, e.g. once the server
+/// assigns our team
+fn sys_skin_add(
+	mut cmds: Commands,
+	textures: Res<Textures>,
+	q_changed: Query<(Entity, &Skin, &Transform), Changed<Skin>>,
 ) {
-	let statics = &statics.0;
-	let step_secs = tick.interval.as_secs_f32();
+	let textures = &textures.0;
 
-	for (col, mut pos, vel) in &mut q_player {
-		if vel.v == Vec2::ZERO {
+	for (ent, skin, t) in &q_changed {
+		let texture_atlas = unwrap!(textures.get(&skin.0), {
+			warn!("Unknown skin '{}'", skin.0);
 			continue;
-		}
-
-		//info!("    ----");
-
-		let mut max_toi = step_secs;
-		let mut v = vel.clone();
-		let mut limit = 8;
-		while max_toi > 0.0 && limit > 0 {
-			limit -= 1;
-
-			//info!("pos: {:?}; v: {:?}; max_toi: {}", pos.p, v.v, max_toi);
-
-			let margin:f32 = 8192.0 * f32::EPSILON;
-			match toi(&q_statics, &statics, col, &pos, &v, max_toi) {
-				ToiResult::Miss => {
-					pos.p += v.v * max_toi;
-					break;
-				},
-				ToiResult::Contact(contact) => {
-					//info!("contact: {:?}", contact);
-
-					pos.p += contact.norm * (contact.dist + margin);
-					v.v = slide(v.v, contact.norm);
-				},
-				ToiResult::Toi(toi) => {
-					//info!("toi: {:?}", toi);
-
-					max_toi -= toi.toi_sec;
-					pos.p += v.v * toi.toi_sec + toi.norm * margin;
-					v.v = slide(v.v, toi.norm);
-				},
-			}
-		}
-	}
-}
-
-fn spawn_player(mut cmds: Commands, textures: Res<Textures>) {
-	let textures = &textures.0;
+		});
 
-	let animation_indicies = AnimationIndices { first: 0, last: 2, direction: 1 };
-	let mut animation_timer = AnimationTimer(Timer::from_seconds(0.2, TimerMode::Repeating));
-	animation_timer.pause();
-	cmds.spawn((
-		Player::default(),
-		Name::new("Player"),
-		SpriteSheetBundle {
-			texture_atlas: textures.get("player_purple").unwrap().clone(),
+		cmds.entity(ent).insert(SpriteSheetBundle {
+			texture_atlas: texture_atlas.clone(),
 			sprite: TextureAtlasSprite::new(1),
-			transform: Transform::from_xyz(0.0, 0.0, Layer::PLAYER),
+			transform: *t,
 			..default()
-		},
-		Collidable::circle(96.0),
-		Position::ZERO,
-		Velocity::ZERO,
-		animation_indicies,
-		animation_timer
-	));
-}
-
-#[derive(Component, Default, Reflect)]
-#[reflect(Component)]
-struct Shot {
-	bounces: u8,
-}
-
-fn spawn_shot(
-	cmds: &mut Commands,
-	textures: &Res<Textures>,
-	sound: Handle<AudioSource>,
-	pos: Vec2,
-	dir: Vec2,
-) {
-	let textures = &textures.0;
-	let speed: f32 = 2700.0;
-
-	let shot = cmds.spawn((
-		Shot{ bounces: 3 },
-		Name::new("Shot"),
-		SpriteSheetBundle {
-			texture_atlas: textures.get("shot_purple").unwrap().clone(),
-			transform: Transform::from_xyz(pos.x, pos.y, Layer::SHOT),
-			..default()
-		},
-		Collidable::circle(26.0),
-		Position::from(pos),
-		Velocity::from(dir * speed),
-	)).id();
-	let audio = cmds.spawn((
-		AudioBundle {
-			source: sound,
-			..default()
-		},
-	)).id();
-	cmds
-		.entity(shot)
-		.add_child(audio);
+		});
+	}
 }
 
-fn sys_spawn_shot(
+fn sys_shot_sound_add(
 	mut cmds: Commands,
 	sounds: Res<Sounds>,
-	textures: Res<Textures>,
-	tick: Res<TickConfig>,
-	mut q_player: Query<(&Transform, &mut Player)>
+	q_added: Query<Entity, Added<Shot>>,
 ) {
-	let step_ns = tick.interval.as_nanos();
-
-	let (player_t, mut player) = q_player.single_mut();
-	let dir = player_t.right().truncate();
-	let pos = player_t.translation.truncate() + dir * (96.0 + 26.0);
-
 	let sound = sounds.0.get("laser/1").unwrap();
-	if let Some(acc) = &mut player.shot_acc {
-		for _ in acc.advance(step_ns as u64) {
-			spawn_shot(&mut cmds, &textures, sound.clone(), pos, dir);
-		}
+
+	for ent in &q_added {
+		let audio = cmds.spawn((
+			AudioBundle {
+				source: sound.clone(),
+				..default()
+			},
+		)).id();
+		cmds
+			.entity(ent)
+			.add_child(audio);
 	}
 }
 
-fn sys_spawn_shots(
-	mut cmds: Commands,
-	sounds: Res<Sounds>,
-	textures: Res<Textures>,
-) {
-	let sound = sounds.0.get("laser/1").unwrap();
-
+fn sys_spawn_shots(mut cmds: Commands) {
 	let half_range = 0;
 	for i in -half_range..half_range {
 		let f = 0.001 * i as f32;
 		let pos = Vec2::new(f, f);
 		let dir = Vec2::from_angle(f);
-		spawn_shot(&mut cmds, &textures, sound.clone(), pos, dir);
+		cmds.spawn(sim::shot_bundle(Team::default(), None, pos, dir * sim::SHOT_SPEED));
 	}
 }
 
@@ -455,61 +447,6 @@ fn spawn_bg(mut cmds: Commands, textures: Res<Textures>) {
 	mk_dirt(-260.0, 240.0);
 }
 
-#[derive(Component, Default, Reflect)]
-struct Static;
-
-fn spawn_statics(mut cmds: Commands, textures: Res<Textures>) {
-	let textures = &textures.0;
-
-	{
-		let mut mk_wall = |name, texture, x, y, w, h, r| {
-			cmds.spawn((
-				Static,
-				Name::new(name),
-				SpriteSheetBundle {
-					texture_atlas: textures.get(texture).unwrap().clone(),
-					transform: Transform
-						::from_rotation(Quat::from_rotation_z(r))
-						.with_translation(Vec3::new(x, y, Layer::STATIC)),
-					..default()
-				},
-				Collidable::aa_rect(w, h),
-				Position::new(x, y),
-			));
-		};
-
-		mk_wall("Wall - Left", "wall_out_left", -1184.0, 0.0, 96.0, 3840.0, 0.0);
-		mk_wall("Wall - Right", "wall_out_right", 1184.0, 0.0, 96.0, 3840.0, 0.0);
-		mk_wall("Wall - Top", "wall_out_top", 0.0, 1824.0, 2560.0, 96.0, TURN_4_RADS);
-		mk_wall("Wall - Bottom", "wall_out_bottom", 0.0, -1824.0, 2560.0, 96.0, TURN_4_RADS);
-		mk_wall("Wall - Horizontal", "wall_in_horizontal", -196.0, -1149.5, 1066.0, 299.0, TURN_4_RADS);
-		mk_wall("Wall - Verticle", "wall_in_verticle", 702.0, 288.5, 296.0, 2465.0, 0.0);
-	}
-
-	{
-		let mut mk_bush = |x, y| {
-			cmds.spawn((
-				Static,
-				Name::new(format!("Bush ({}, {})", x, y)),
-				SpriteSheetBundle {
-					texture_atlas: textures.get("bush").unwrap().clone(),
-					transform: Transform::from_xyz(x, y, Layer::STATIC),
-					..default()
-				},
-				Collidable::circle(128.0),
-				Position::new(x, y),
-			));
-		};
-
-		mk_bush(-128.0, 1228.0);
-		mk_bush(128.0, 1100.0);
-		mk_bush(-512.0, 64.0);
-		mk_bush(192.0, -512.0);
-		mk_bush(64.0, -640.0);
-		mk_bush(760.0, -1400.0);
-	}
-}
-
 fn load_assets(
 	mut sounds: ResMut<Sounds>,
 	mut textures: ResMut<Textures>,
@@ -603,15 +540,19 @@ fn sys_fps(mut metric: Local<Metric>, time: Res<Time>) {
 	}
 }
 
-fn sys_tps(mut metric: Local<Metric>, tick: Res<TickConfig>) {
+fn sys_tps(mut metric: Local<Metric>, tick: Res<TickConfig>, clock: Res<ClockSync>) {
 	metric.sample(tick.interval.as_secs_f32());
 	if metric.total() >= 1.0 {
 		info!(
-			"ticks:{}, tps:{:.2}, min:{:.2}ms, max:{:.2}ms",
+			"ticks:{}, tps:{:.2}, min:{:.2}ms, max:{:.2}ms, offset:{:.2}, target:{:.2}, rtt:{:.1}ms, jitter:{:.1}ms",
 			metric.count(),
 			1.0 / metric.avg(),
 			metric.min() * 1000.0,
 			metric.max() * 1000.0,
+			clock.offset_ticks,
+			clock.target_ticks,
+			clock.rtt_ms,
+			clock.jitter_ms,
 		);
 		metric.reset();
 	}
@@ -620,7 +561,8 @@ fn sys_tps(mut metric: Local<Metric>, tick: Res<TickConfig>) {
 fn sys_apply_input(
 	input: Res<PlayerInput>,
 	mut debug: ResMut<Debug>,
-	mut q_player: Query<(&mut Velocity, &mut Transform, &mut AnimationTimer, &mut Player)>,
+	mut net_graph: ResMut<NetGraph>,
+	mut q_player: Query<&mut Intent, With<LocalPlayer>>,
 	mut q_windows: Query<&mut Window, With<PrimaryWindow>>,
 ) {
 	let mut win = q_windows.single_mut();
@@ -636,26 +578,26 @@ fn sys_apply_input(
 		debug.enabled = input.debug;
 	}
 
-	let (mut player_v, mut player_t, mut player_a, mut player) = q_player.single_mut();
-
-	// shooting
-
-	if input.primary && player.shot_acc.is_none() {
-		player.shot_acc = Some(Accumulator::ready_from_millis(100));
-	} else if !input.primary && player.shot_acc.is_some() {
-		player.shot_acc = None;
+	if input.net_graph != net_graph.enabled {
+		net_graph.enabled = input.net_graph;
 	}
 
-	// player transform
-
-	player_v.v = 900.0f32 * input.dir;
-	player_t.rotation = Quat::from_rotation_z(input.face_turns * TURN_RADS);
-
-	// animation
+	let mut intent = q_player.single_mut();
+	*intent = Intent {
+		dir: input.dir,
+		face_turns: input.face_turns,
+		primary: input.primary,
+	};
+}
 
-	if input.dir == Vec2::ZERO {
-		player_a.pause();
-	} else {
-		player_a.unpause();
+fn sys_player_animation(
+	mut q_player: Query<(&Velocity, &mut AnimationTimer), With<Player>>,
+) {
+	for (vel, mut anim) in &mut q_player {
+		if vel.v == Vec2::ZERO {
+			anim.pause();
+		} else {
+			anim.unpause();
+		}
 	}
 }
@@ -1,60 +1,38 @@
 use bevy::prelude::*;
 use parry2d::na;
-use std::ops::{Add, Sub};
+use std::f32::consts::TAU;
 
 #[derive(Component, Clone, Default, Reflect)]
 #[reflect(Component)]
 pub struct Position {
 	pub p: Vec2,
+	/// Counter-clockwise rotation about `p`, in turns, like `Facing`
+	pub turns: f32,
 }
 
 impl Position {
 	pub const fn new(x: f32, y: f32) -> Self {
-		Position { p: Vec2::new(x, y) }
+		Position { p: Vec2::new(x, y), turns: 0.0 }
 	}
 
 	pub const ZERO: Self = Self::new(0.0, 0.0);
 
-	pub fn to_iso(&self) -> na::Isometry2<f32> {
-		na::Isometry2::new(na::Vector2::new(self.p.x, self.p.y), 0.0)
-	}
-}
-
-impl From<Vec2> for Position {
-	fn from(p: Vec2) -> Self {
-		Position{ p }
-	}
-}
-
-impl Add for Position {
-	type Output = Self;
-
-	fn add(self, other: Self) -> Self {
-		Self { p: self.p + other.p }
+	pub const fn with_rotation(self, turns: f32) -> Self {
+		Position { turns, ..self }
 	}
-}
 
-impl<'a, 'b> Add<&'b Position> for &'a Position {
-	type Output = Position;
-
-	fn add(self, other: &'b Position) -> Position {
-		Position { p: self.p + other.p }
+	pub fn to_iso(&self) -> na::Isometry2<f32> {
+		na::Isometry2::new(na::Vector2::new(self.p.x, self.p.y), self.turns * TAU)
 	}
-}
 
-impl Sub for Position {
-	type Output = Self;
-
-	fn sub(self, other: Self) -> Self {
-		Self { p: self.p - other.p }
+	pub fn to_quat(&self) -> Quat {
+		Quat::from_rotation_z(self.turns * TAU)
 	}
 }
 
-impl<'a, 'b> Sub<&'b Position> for &'a Position {
-	type Output = Position;
-
-	fn sub(self, other: &'b Position) -> Position {
-		Position { p: self.p - other.p }
+impl From<Vec2> for Position {
+	fn from(p: Vec2) -> Self {
+		Position{ p, turns: 0.0 }
 	}
 }
 
@@ -82,9 +60,31 @@ impl From<Vec2> for Velocity {
 	}
 }
 
-pub fn sys_write_back(mut q: Query<(&Position, &mut Transform)>) {
-	for (pos, mut t) in q.iter_mut() {
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct Facing {
+	pub turns: f32,
+}
+
+impl Facing {
+	pub fn dir(&self) -> Vec2 {
+		Vec2::from_angle(self.turns * TAU)
+	}
+
+	pub fn to_quat(&self) -> Quat {
+		Quat::from_rotation_z(self.turns * TAU)
+	}
+}
+
+/// Where `Facing` is present, it decides which way things are drawn
+pub fn sys_write_back(mut q: Query<(&Position, Option<&Facing>, &mut Transform)>) {
+	for (pos, facing, mut t) in q.iter_mut() {
 		t.translation.x = pos.p.x;
 		t.translation.y = pos.p.y;
+
+		t.rotation = match facing {
+			Some(facing) => facing.to_quat(),
+			None => pos.to_quat(),
+		};
 	}
 }
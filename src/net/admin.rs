@@ -0,0 +1,449 @@
+use bevy::{
+	ecs::system::SystemParam,
+	prelude::*,
+};
+use naia_bevy_server::{events::MessageEvents, UserKey};
+use naia_bevy_shared::{ReceiveEvents, WorldProxyMut};
+use std::{
+	collections::{BTreeSet, HashMap},
+	fs::{self, OpenOptions},
+	io::{self, BufRead, Write},
+	net::{IpAddr, SocketAddr},
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::{
+		atomic::Ordering,
+		mpsc::{self, Receiver},
+		Mutex,
+	},
+	thread,
+	time::{Duration, Instant},
+};
+use crate::{
+	movement::{Position, Velocity},
+	player::Player,
+	sim::Shot,
+};
+use super::{
+	config::{AdminChannel, CmdStreamChannel, TICK_INTERVAL},
+	discovery::ServerInfo,
+	msg,
+	server::{ServerContext, ShutdownSignal},
+	session::Sessions,
+};
+
+// long enough for the kick notice, or rejection, to reach them before the
+// connection drops
+pub(super) const KICK_GRACE: Duration = Duration::from_millis(500);
+
+// wrong admin passwords allowed from one address before it's locked out for a while
+const ADMIN_MAX_FAILURES: u32 = 5;
+const ADMIN_LOCKOUT: Duration = Duration::from_secs(300);
+
+// the only map there is, for now
+const MAPS: &[&str] = &["default"];
+
+/// Lets whoever runs the server manage it while it's up, from the console or
+/// remotely with the admin password
+pub struct AdminPlugin;
+
+impl Plugin for AdminPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<AdminPassword>()
+			.init_resource::<Bans>()
+			.add_systems(Update, (
+				sys_admin_console,
+				sys_admin_remote,
+				sys_kick,
+			).chain().in_set(ReceiveEvents));
+	}
+}
+
+/// Password for remote admin commands; without one, they're refused
+#[derive(Clone, Default, Resource)]
+pub struct AdminPassword(pub Option<String>);
+
+/// Lines typed into the server's stdin
+#[derive(Resource)]
+pub struct AdminConsole(Mutex<Receiver<String>>);
+
+impl AdminConsole {
+	/// Reads stdin on a thread of its own, so the server never waits on it
+	pub fn stdin() -> Self {
+		let (tx, rx) = mpsc::channel();
+		thread::spawn(move || {
+			for line in io::stdin().lock().lines() {
+				let line = unwrap!(line.ok(), { break; });
+				if tx.send(line).is_err() {
+					break;
+				}
+			}
+		});
+
+		AdminConsole(Mutex::new(rx))
+	}
+}
+
+/// Addresses that aren't welcome back, kept in a file so they stay banned
+#[derive(Default, Resource)]
+pub struct Bans {
+	path: Option<PathBuf>,
+	addrs: BTreeSet<IpAddr>,
+}
+
+impl Bans {
+	/// Reads one address per line; blank lines and `#` comments are skipped. A
+	/// missing file is just an empty list, to be created by the first ban.
+	pub fn load(path: &Path) -> io::Result<Self> {
+		let mut addrs = BTreeSet::new();
+
+		let text = match fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+			Err(e) => return Err(e),
+		};
+		for (i, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let addr = line.parse().map_err(|_| io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("{}:{}: expected an IP address", path.display(), i + 1),
+			))?;
+			addrs.insert(addr);
+		}
+
+		Ok(Bans { path: Some(path.to_path_buf()), addrs })
+	}
+
+	pub fn contains(&self, addr: &IpAddr) -> bool {
+		self.addrs.contains(addr)
+	}
+
+	/// Bans `addr`, and writes it down. Returns whether it's newly banned.
+	pub fn add(&mut self, addr: IpAddr) -> io::Result<bool> {
+		if !self.addrs.insert(addr) {
+			return Ok(false);
+		}
+
+		if let Some(path) = &self.path {
+			let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+			writeln!(file, "{}", addr)?;
+		}
+		Ok(true)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BanTarget {
+	Client(u32),
+	Addr(IpAddr),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+	Status,
+	Kick(u32),
+	Ban(BanTarget),
+	Map(String),
+	Say(String),
+	Tickrate,
+	Quit,
+}
+
+impl FromStr for Command {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		let (name, arg) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+		let arg = arg.trim();
+
+		let required = |usage: &str| if arg.is_empty() {
+			Err(format!("usage: {}", usage))
+		} else {
+			Ok(arg.to_string())
+		};
+
+		match name {
+			"status" => Ok(Command::Status),
+			"kick" => arg.parse()
+				.map(Command::Kick)
+				.map_err(|_| "usage: kick <id>".into()),
+			"ban" => {
+				let target = arg.parse().map(BanTarget::Client)
+					.or_else(|_| arg.parse().map(BanTarget::Addr))
+					.or_else(|_| arg.parse().map(|a: SocketAddr| BanTarget::Addr(a.ip())))
+					.map_err(|_| "usage: ban <id|addr>")?;
+				Ok(Command::Ban(target))
+			},
+			"map" => required("map <name>").map(Command::Map),
+			"say" => required("say <text>").map(Command::Say),
+			"tickrate" => Ok(Command::Tickrate),
+			"quit" => Ok(Command::Quit),
+			_ => Err(format!(
+				"unknown command '{}'; expected status, kick, ban, map, say, tickrate or quit",
+				name,
+			)),
+		}
+	}
+}
+
+/// Everything admin commands act on
+#[derive(SystemParam)]
+struct Admin<'w, 's> {
+	cmds: Commands<'w, 's>,
+	server: ResMut<'w, naia_server::Server<Entity>>,
+	ctx: ResMut<'w, ServerContext>,
+	sessions: ResMut<'w, Sessions>,
+	bans: ResMut<'w, Bans>,
+	signal: Res<'w, ShutdownSignal>,
+	info: ResMut<'w, ServerInfo>,
+	q_players: Query<'w, 's, (&'static mut Position, &'static mut Velocity), With<Player>>,
+	q_shots: Query<'w, 's, Entity, With<Shot>>,
+}
+
+impl Admin<'_, '_> {
+	/// Carries out `command`, returning what to tell whoever issued it
+	fn run(&mut self, command: Command) -> Vec<String> {
+		match command {
+			Command::Status => self.status(),
+			Command::Kick(client_id) => match self.user_of(client_id) {
+				Some(uid) => vec![self.kick(uid, "kicked by admin")],
+				None => vec![format!("no client {} connected", client_id)],
+			},
+			Command::Ban(BanTarget::Client(client_id)) => {
+				let uid = unwrap!(self.user_of(client_id), {
+					return vec![format!("no client {} connected", client_id)];
+				});
+				let addr = self.server.user(&uid).address().ip();
+				self.ban(addr)
+			},
+			Command::Ban(BanTarget::Addr(addr)) => self.ban(addr),
+			Command::Map(name) => self.change_map(&name),
+			Command::Say(text) => {
+				let msg = msg::ChatBroadcast {
+					client_id: 0,
+					name: "server".into(),
+					text: text.clone(),
+					team_only: false,
+				};
+				self.server.broadcast_message::<CmdStreamChannel, msg::ChatBroadcast>(&msg);
+				vec![format!("server: {}", text)]
+			},
+			Command::Tickrate => {
+				let avg = self.server.average_tick_duration().as_secs_f32();
+				let target = TICK_INTERVAL.as_secs_f32();
+				vec![format!("{:.2} ticks/s (target {:.2})", 1.0 / avg, 1.0 / target)]
+			},
+			Command::Quit => {
+				self.signal.0.store(true, Ordering::Relaxed);
+				vec!["shutting down".into()]
+			},
+		}
+	}
+
+	fn user_of(&self, client_id: u32) -> Option<UserKey> {
+		self.sessions.connected()
+			.find(|(_, s)| s.client_id == client_id)
+			.map(|(uid, _)| uid)
+	}
+
+	fn status(&self) -> Vec<String> {
+		let mut lines = vec![format!(
+			"{}/{} players, tick {}",
+			self.sessions.connected().count(),
+			self.sessions.max_players,
+			self.server.current_tick(),
+		)];
+		for (uid, s) in self.sessions.connected() {
+			lines.push(format!(
+				"  {:>3} {:<24} {} {:.0}ms",
+				s.client_id,
+				s.name,
+				self.server.user(&uid).address(),
+				self.server.rtt(&uid).unwrap_or(0.0),
+			));
+		}
+		lines
+	}
+
+	/// Tells `uid` why they're going, and has them disconnected shortly after.
+	/// Their session ends right away, rather than waiting for them to resume it.
+	fn kick(&mut self, uid: UserKey, reason: &str) -> String {
+		let msg = msg::Shutdown::new(reason);
+		self.server.send_message::<CmdStreamChannel, msg::Shutdown>(&uid, &msg);
+		self.ctx.kicking.insert(uid, Instant::now() + KICK_GRACE);
+
+		let line = match self.sessions.of_user(&uid) {
+			Some(s) => format!("{} {} (client {})", reason, s.name, s.client_id),
+			None => format!("{} {}", reason, self.server.user(&uid).address()),
+		};
+
+		// expires at once, so `sys_expire_sessions` removes their player too
+		self.sessions.suspend(&uid, Instant::now());
+		line
+	}
+
+	fn ban(&mut self, addr: IpAddr) -> Vec<String> {
+		let mut lines = vec![match self.bans.add(addr) {
+			Ok(true) => format!("banned {}", addr),
+			Ok(false) => format!("{} is already banned", addr),
+			Err(e) => format!("banned {}, but failed to save it: {}", addr, e),
+		}];
+
+		let users: Vec<_> = self.sessions.connected()
+			.map(|(uid, _)| uid)
+			.filter(|uid| self.server.user(uid).address().ip() == addr)
+			.collect();
+		for uid in users {
+			lines.push(self.kick(uid, "banned by admin"));
+		}
+		lines
+	}
+
+	/// There's only the one map, so changing to it starts the round over
+	fn change_map(&mut self, name: &str) -> Vec<String> {
+		if !MAPS.contains(&name) {
+			return vec![format!("unknown map '{}'; expected one of {}", name, MAPS.join(", "))];
+		}
+
+		for (mut pos, mut vel) in &mut self.q_players {
+			*pos = Position::ZERO;
+			*vel = Velocity::ZERO;
+		}
+		for ent in &self.q_shots {
+			self.cmds.entity(ent).despawn_recursive();
+		}
+		self.info.map = name.to_string();
+		vec![format!("restarted on {}", name)]
+	}
+}
+
+fn sys_admin_console(console: Option<Res<AdminConsole>>, mut admin: Admin) {
+	let console = unwrap!(console, { return; });
+	let rx = console.0.lock().expect("admin console lock poisoned");
+
+	while let Ok(line) = rx.try_recv() {
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let lines = match line.parse() {
+			Ok(command) => admin.run(command),
+			Err(e) => vec![e],
+		};
+		for line in lines {
+			println!("{}", line);
+		}
+	}
+}
+
+/// Wrong admin passwords from one address, since it last got one right
+#[derive(Default)]
+struct Failures {
+	count: u32,
+	locked_until: Option<Instant>,
+}
+
+/// Compares secrets in time that depends only on their lengths, so guesses
+/// can't be refined by timing the replies
+fn same_secret(a: &str, b: &str) -> bool {
+	let (a, b) = (a.as_bytes(), b.as_bytes());
+	let len = a.len().max(b.len());
+	let diff = (0..len).fold(a.len() ^ b.len(), |diff, i| {
+		let x = a.get(i).copied().unwrap_or(0);
+		let y = b.get(i).copied().unwrap_or(0);
+		diff | (x ^ y) as usize
+	});
+	diff == 0
+}
+
+fn sys_admin_remote(
+	mut event_sets: EventReader<MessageEvents>,
+	password: Res<AdminPassword>,
+	mut admin: Admin,
+	mut failures: Local<HashMap<IpAddr, Failures>>,
+) {
+	let now = Instant::now();
+	failures.retain(|_, f| f.locked_until.is_none_or(|t| now < t));
+
+	for events in event_sets.read() {
+		for (uid, msg) in events.read::<AdminChannel, msg::AdminCommand>() {
+			// only let in to hear why they're rejected, not to guess passwords
+			if admin.ctx.rejections.contains_key(&uid) {
+				continue;
+			}
+
+			let addr = admin.server.user(&uid).address();
+			let failed = failures.entry(addr.ip()).or_default();
+			let lines = if failed.locked_until.is_some() {
+				vec!["too many wrong passwords; try again later".into()]
+			} else if password.0.as_ref().is_none_or(|p| !same_secret(p, &msg.password)) {
+				failed.count += 1;
+				if failed.count >= ADMIN_MAX_FAILURES {
+					failed.locked_until = Some(now + ADMIN_LOCKOUT);
+					println!("Locked {} out of admin commands for {:?}", addr, ADMIN_LOCKOUT);
+				} else {
+					println!("Refused admin command from {}", addr);
+				}
+				vec!["access denied".into()]
+			} else {
+				failures.remove(&addr.ip());
+				println!("Admin command from {}: {}", addr, msg.command);
+				match msg.command.parse() {
+					Ok(command) => admin.run(command),
+					Err(e) => vec![e],
+				}
+			};
+
+			for line in lines {
+				let reply = msg::AdminReply { text: line };
+				admin.server.send_message::<CmdStreamChannel, msg::AdminReply>(&uid, &reply);
+			}
+		}
+	}
+}
+
+/// Disconnects anyone kicked, once they've had a moment to hear about it
+fn sys_kick(world: &mut World) {
+	let now = Instant::now();
+	let due: Vec<UserKey> = {
+		let mut ctx = unwrap!(world.get_resource_mut::<ServerContext>(), { return; });
+		let due = ctx.kicking.iter()
+			.filter(|(_, t)| now >= **t)
+			.map(|(uid, _)| *uid)
+			.collect();
+		ctx.kicking.retain(|_, t| now < *t);
+		due
+	};
+	if due.is_empty() {
+		return;
+	}
+
+	world.resource_scope(|world, mut server: Mut<naia_server::Server<Entity>>| {
+		for uid in due {
+			if server.user_exists(&uid) {
+				server.user_mut(&uid).disconnect(world.proxy_mut());
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn compares_secrets() {
+		assert!(same_secret("hunter2", "hunter2"));
+		assert!(same_secret("", ""));
+		assert!(!same_secret("hunter2", "hunter3"));
+		assert!(!same_secret("hunter2", "hunter"));
+		assert!(!same_secret("hunter", "hunter2"));
+		assert!(!same_secret("a", "a\0"));
+	}
+}
@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use std::{
+	collections::HashMap,
+	fs,
+	io,
+	path::Path,
+};
+use super::msg;
+
+// long enough for anyone's handle, short enough to fit over their head
+const MAX_NAME_LEN: usize = 24;
+
+/// Who a client turned out to be, once authenticated
+#[derive(Clone, Debug)]
+pub struct Identity {
+	pub name: String,
+}
+
+/// Decides who may join, and under what name. Implement this to plug in custom
+/// validation; `Err` holds the reason shown to the rejected client.
+pub trait Authenticator: Send + Sync + 'static {
+	fn authenticate(&self, auth: &msg::Auth) -> Result<Identity, String>;
+}
+
+/// The server's authenticator; anyone may join if none is inserted
+#[derive(Resource)]
+pub struct ServerAuth(pub Box<dyn Authenticator>);
+
+impl Default for ServerAuth {
+	fn default() -> Self {
+		ServerAuth(Box::new(Open))
+	}
+}
+
+/// Lets everyone in, as whoever they say they are
+pub struct Open;
+
+impl Authenticator for Open {
+	fn authenticate(&self, auth: &msg::Auth) -> Result<Identity, String> {
+		Ok(Identity { name: clean_name(&auth.name)? })
+	}
+}
+
+/// Lets in anyone who knows the password
+pub struct SharedSecret(pub String);
+
+impl Authenticator for SharedSecret {
+	fn authenticate(&self, auth: &msg::Auth) -> Result<Identity, String> {
+		if auth.token != self.0 {
+			return Err("incorrect password".into());
+		}
+		Ok(Identity { name: clean_name(&auth.name)? })
+	}
+}
+
+/// Lets in only known tokens, each under the name it was issued for
+pub struct Allowlist {
+	names: HashMap<String, String>,
+}
+
+impl Allowlist {
+	/// Reads `TOKEN NAME` pairs, one per line; blank lines and `#` comments are skipped
+	pub fn load(path: &Path) -> io::Result<Self> {
+		let mut names = HashMap::new();
+
+		for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let (token, name) = line.split_once(char::is_whitespace)
+				.ok_or_else(|| io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("{}:{}: expected TOKEN NAME", path.display(), i + 1),
+				))?;
+			names.insert(token.to_string(), name.trim().to_string());
+		}
+
+		Ok(Allowlist { names })
+	}
+}
+
+impl Authenticator for Allowlist {
+	fn authenticate(&self, auth: &msg::Auth) -> Result<Identity, String> {
+		let name = unwrap!(self.names.get(&auth.token), {
+			return Err("unknown token".into());
+		});
+		Ok(Identity { name: clean_name(name)? })
+	}
+}
+
+/// Trims `name`, and refuses anything nobody could read
+pub fn clean_name(name: &str) -> Result<String, String> {
+	let name = name.trim();
+
+	if name.chars().count() > MAX_NAME_LEN {
+		return Err(format!("name is longer than {} characters", MAX_NAME_LEN));
+	}
+	if name.chars().any(char::is_control) {
+		return Err("name contains control characters".into());
+	}
+
+	Ok(name.to_string())
+}
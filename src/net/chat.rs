@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+use naia_bevy_client::Client;
+use naia_bevy_server::{Server, UserKey};
+use naia_bevy_shared::ReceiveEvents;
+use std::{
+	collections::{HashMap, VecDeque},
+	time::{Duration, Instant},
+};
+use crate::tick_schedule::TickSchedule;
+use super::{
+	config::{AdminChannel, ChatChannel, CmdStreamChannel},
+	msg,
+	session::Sessions,
+};
+
+pub const MAX_CHAT_LEN: usize = 160;
+
+// a burst of this many messages, then one more every `CHAT_REFILL`
+const CHAT_BURST: f32 = 5.0;
+const CHAT_REFILL: Duration = Duration::from_secs(1);
+
+// lines kept around for the chat box to show
+const CHAT_HISTORY: usize = 50;
+
+/// Sends what the local player types, and collects what everyone says
+pub struct ChatClientPlugin;
+
+impl Plugin for ChatClientPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<ChatLog>()
+			.add_event::<SendChat>()
+			.add_systems(TickSchedule::Network, sys_chat_recv.in_set(ReceiveEvents))
+			.add_systems(TickSchedule::PostTicks, sys_chat_send);
+	}
+}
+
+/// Relays chat between players, within limits
+pub struct ChatServerPlugin;
+
+impl Plugin for ChatServerPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_systems(Update, sys_chat_relay.in_set(ReceiveEvents));
+	}
+}
+
+/// Fire to have the local player say something
+#[derive(Clone, Debug, Event)]
+pub struct SendChat {
+	pub text: String,
+	pub team_only: bool,
+}
+
+pub struct ChatLine {
+	pub name: String,
+	pub text: String,
+	pub team_only: bool,
+	pub at: Instant,
+}
+
+/// Recent chat, oldest first
+#[derive(Default, Resource)]
+pub struct ChatLog {
+	pub lines: VecDeque<ChatLine>,
+}
+
+impl ChatLog {
+	fn push(&mut self, name: String, text: String, team_only: bool) {
+		if self.lines.len() >= CHAT_HISTORY {
+			self.lines.pop_front();
+		}
+		self.lines.push_back(ChatLine { name, text, team_only, at: Instant::now() });
+	}
+}
+
+fn sys_chat_send(mut client: Client, mut events: EventReader<SendChat>) {
+	for SendChat { text, team_only } in events.read() {
+		if !client.is_connected() {
+			continue;
+		}
+
+		// "/admin <password> <command>" goes to the server's admin console instead
+		if let Some(rest) = text.strip_prefix("/admin ") {
+			let rest = rest.trim();
+			let (password, command) = rest.split_once(' ').unwrap_or((rest, ""));
+			let msg = msg::AdminCommand { password: password.into(), command: command.trim().into() };
+			client.send_message::<AdminChannel, msg::AdminCommand>(&msg);
+			continue;
+		}
+
+		let msg = msg::ChatSend { text: text.clone(), team_only: *team_only };
+		client.send_message::<ChatChannel, msg::ChatSend>(&msg);
+	}
+}
+
+fn sys_chat_recv(
+	mut event_sets: EventReader<naia_bevy_client::events::MessageEvents>,
+	mut log: ResMut<ChatLog>,
+) {
+	for events in event_sets.read() {
+		for msg in events.read::<CmdStreamChannel, msg::ChatBroadcast>() {
+			info!("{}{}: {}", if msg.team_only { "(team) " } else { "" }, msg.name, msg.text);
+			log.push(msg.name, msg.text, msg.team_only);
+		}
+		for msg in events.read::<CmdStreamChannel, msg::AdminReply>() {
+			info!("admin: {}", msg.text);
+			log.push("admin".into(), msg.text, false);
+		}
+	}
+}
+
+/// Token bucket; allows short bursts, but not a sustained flood
+struct RateLimit {
+	tokens: f32,
+	updated: Instant,
+}
+
+impl RateLimit {
+	fn new(now: Instant) -> Self {
+		RateLimit { tokens: CHAT_BURST, updated: now }
+	}
+
+	fn take(&mut self, now: Instant) -> bool {
+		let refilled = (now - self.updated).as_secs_f32() / CHAT_REFILL.as_secs_f32();
+		self.tokens = f32::min(self.tokens + refilled, CHAT_BURST);
+		self.updated = now;
+
+		if self.tokens < 1.0 {
+			return false;
+		}
+		self.tokens -= 1.0;
+		true
+	}
+}
+
+/// Strips what shouldn't be shown, and cuts it down to size
+fn clean_text(text: &str) -> Option<String> {
+	let text: String = text.chars()
+		.filter(|c| !c.is_control())
+		.collect();
+	let text: String = text.trim().chars().take(MAX_CHAT_LEN).collect();
+
+	(!text.is_empty()).then_some(text)
+}
+
+fn sys_chat_relay(
+	mut server: Server,
+	mut event_sets: EventReader<naia_bevy_server::events::MessageEvents>,
+	sessions: Res<Sessions>,
+	mut limits: Local<HashMap<UserKey, RateLimit>>,
+) {
+	let now = Instant::now();
+	limits.retain(|uid, _| sessions.of_user(uid).is_some());
+
+	for events in event_sets.read() {
+		for (uid, msg) in events.read::<ChatChannel, msg::ChatSend>() {
+			let sender = unwrap!(sessions.of_user(&uid), { continue; });
+			if !limits.entry(uid).or_insert_with(|| RateLimit::new(now)).take(now) {
+				println!("Dropped chat from {} (client {}): too fast", sender.name, sender.client_id);
+				continue;
+			}
+			let text = unwrap!(clean_text(&msg.text), { continue; });
+
+			let msg = msg::ChatBroadcast {
+				client_id: sender.client_id,
+				name: sender.name.clone(),
+				text,
+				team_only: msg.team_only,
+			};
+			let listeners = sessions.connected()
+				.filter(|(_, s)| !msg.team_only || s.team == sender.team);
+			for (other, _) in listeners {
+				server.send_message::<CmdStreamChannel, msg::ChatBroadcast>(&other, &msg);
+			}
+		}
+	}
+}
@@ -1,22 +1,21 @@
 use bevy::prelude::*;
-use naia_bevy_shared::ReceiveEvents;
+use naia_bevy_shared::{ReceiveEvents, Tick};
 use crate::{
-	input::interpret::PlayerInput,
+	movement::{Facing, Position, Velocity},
 	tick_schedule::{
 		TickSchedule,
 		single_thread_schedule,
 	},
-	player::Player,
+	player::{Intent, LocalPlayer, PlayerId, Team},
+	sim::{player_bundle, Proxy, Shot, shot_bundle, Skin},
 };
 use naia_bevy_client::{
 	events::{
-		ClientTickEvent,
 		ConnectEvent,
 		DisconnectEvent,
 		ErrorEvent,
 		MessageEvents,
 		RejectEvent,
-		ServerTickEvent,
 	},
 	transport::udp,
 	Client,
@@ -24,89 +23,216 @@ use naia_bevy_client::{
 	Plugin as NaiaClientPlugin,
 };
 use std::{
-	collections::HashMap,
-	f32::consts::TAU,
+	collections::{HashMap, VecDeque},
+	net::SocketAddr,
+	time::{Duration, Instant},
 };
 use super::{
+	chat::ChatClientPlugin,
+	clock::ClockSyncPlugin,
 	config::{
-		self,
 		CmdStreamChannel,
+		CONNECTION_TIMEOUT,
+		INPUT_REDUNDANCY,
+		InputReplChannel,
 		InputSrcChannel,
-	}, msg, peer::*
+		Link,
+	},
+	interp::InterpolationPlugin,
+	msg,
+	peer::*,
+	predict::PredictionPlugin,
+	repl::{NetFacing, NetPlayer, NetPosition, NetShot, NetVelocity},
+	stats::{BANDWIDTH_WINDOW, NetStatsPlugin},
 };
 
+// first retry comes quickly, then each one waits twice as long, up to ~16s
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_BACKOFF_DOUBLINGS: u32 = 5;
+
+// connected at least this long, and any earlier trouble is water under the bridge
+const STABLE_CONNECTION: Duration = Duration::from_secs(30);
+
 pub struct NetClientPlugin;
 
 impl Plugin for NetClientPlugin {
 	fn build(&self, app: &mut App) {
+		// insert a `Link` ahead of the plugin to simulate network conditions
+		let link = app.world.get_resource::<Link>().cloned().unwrap_or_default();
+
 		app
 			.insert_resource(TickState::default())
+			.insert_resource(link.interp)
 			.add_plugins(NaiaClientPlugin::new(
-				ClientConfig::default(),
-				config::global_avg(),
+				client_config(),
+				link.protocol(),
 			))
 			.insert_resource(ClientContext::default())
+			.insert_resource(ConnectionState::Connecting)
+			.add_event::<Rejected>()
 			.add_schedule(single_thread_schedule(TickSchedule::Network))
 			.add_systems(TickSchedule::Network, (
 				sys_event_connect,
 				sys_event_disconnect,
 				sys_event_error,
 				sys_event_reject,
+				sys_event_shutdown,
+				sys_event_msg,
+				sys_reconnect,
 			).in_set(ReceiveEvents))
 			.add_systems(TickSchedule::PreTicks, (
-				sys_consume_tick_events,
-			))
+				sys_proxy_add_players,
+				sys_proxy_add_shots,
+				sys_proxy_sync,
+			).chain())
+			.add_systems(TickSchedule::PostTicks, sys_despawn_orphans)
 			.add_schedule(single_thread_schedule(TickSchedule::InputSend))
 			.add_systems(TickSchedule::InputSend, (
 				sys_send_input,
 			))
+			.add_plugins((
+				ChatClientPlugin,
+				ClockSyncPlugin,
+				InterpolationPlugin,
+				NetStatsPlugin,
+				PredictionPlugin,
+			))
 			.add_systems(Update, sys_run_tick_schedules)
 			.add_systems(Startup, sys_connect);
 	}
 }
 
-#[derive(Default, Resource)]
-pub struct ClientContext {
-    pub client_entities: HashMap<u32, Entity>,
+fn client_config() -> ClientConfig {
+	let mut config = ClientConfig::default();
+	config.connection.disconnection_timeout_duration = CONNECTION_TIMEOUT;
+	config.connection.bandwidth_measure_duration = Some(BANDWIDTH_WINDOW);
+	config
 }
 
-fn sys_consume_tick_events(
-	mut state: ResMut<TickState>,
-	mut ticks: EventReader<ServerTickEvent>,
-) {
-	state.ticks_pending += ticks.len();
-	for t in ticks.read() {
-		state.cur_tick = t.0;
-		break;
-	}
-	ticks.clear();
+/// Address of the server to connect to
+#[derive(Clone, Copy, Debug, Resource)]
+pub struct ServerAddr(pub SocketAddr);
+
+/// Who we claim to be when connecting
+#[derive(Clone, Debug, Default, Resource)]
+pub struct Credentials {
+	pub token: String,
+	pub name: String,
 }
 
-pub fn sys_connect(mut client: Client) {
-	let addr = udp_sock_addr((127, 0, 0, 1), 5323);
+/// The server turned us away
+#[derive(Event)]
+pub struct Rejected {
+	pub reason: String,
+}
+
+/// Where we stand with the server
+#[derive(Clone, Copy, Debug, PartialEq, Resource)]
+pub enum ConnectionState {
+	Connecting,
+	Connected,
+	/// Lost the connection; trying again at the given time
+	Reconnecting { retry_at: Instant },
+	/// Gone for good, e.g. rejected or the server shut down
+	Closed,
+}
+
+#[derive(Default, Resource)]
+pub struct ClientContext {
+	/// Our own id, once the server has assigned it
+	pub client_id: Option<u32>,
+	/// Resumes our player if we have to reconnect
+	pub session: Option<u64>,
+	/// Reconnects since the connection was last stable
+	pub retries: u32,
+	pub connected_at: Option<Instant>,
+	/// Proxies of remote players, by client id
+	pub client_entities: HashMap<u32, Entity>,
+}
+
+fn connect(client: &mut Client, addr: SocketAddr, creds: &Credentials, session: Option<u64>) {
 	let sock = udp::Socket::new(&addr, None);
 
 	info!("Connecting to server @ {}...", addr);
-	client.auth(msg::Auth::new("token-content"));
+	client.auth(msg::Auth::new(&creds.token, &creds.name, session));
 	client.connect(sock);
 }
 
+pub fn sys_connect(mut client: Client, addr: Res<ServerAddr>, creds: Res<Credentials>) {
+	connect(&mut client, addr.0, &creds, None);
+}
+
+pub fn sys_reconnect(
+	mut client: Client,
+	mut state: ResMut<ConnectionState>,
+	ctx: Res<ClientContext>,
+	addr: Res<ServerAddr>,
+	creds: Res<Credentials>,
+) {
+	let ConnectionState::Reconnecting { retry_at } = *state else { return; };
+	// the old connection has to be torn down before naia will start another
+	if Instant::now() < retry_at || client.is_connecting() {
+		return;
+	}
+
+	connect(&mut client, addr.0, &creds, ctx.session);
+	*state = ConnectionState::Connecting;
+}
+
 pub fn sys_event_connect(
-	client: Client,
 	mut events: EventReader<ConnectEvent>,
+	mut state: ResMut<ConnectionState>,
+	mut ctx: ResMut<ClientContext>,
+	addr: Res<ServerAddr>,
 ) {
 	for _event in events.read() {
-		if let Ok(server_address) = client.server_address() {
-			info!("Connected to server {}", server_address);
+		info!("Connected to server {}", addr.0);
+		*state = ConnectionState::Connected;
+		ctx.connected_at = Some(Instant::now());
+	}
+}
+
+pub fn sys_event_shutdown(
+	mut client: Client,
+	mut state: ResMut<ConnectionState>,
+	mut event_sets: EventReader<MessageEvents>,
+) {
+	for events in event_sets.read() {
+		for msg in events.read::<CmdStreamChannel, msg::Shutdown>() {
+			info!("Server closed the connection: {}", msg.reason);
+			*state = ConnectionState::Closed;
+			if client.is_connected() {
+				client.disconnect();
+			}
 		}
 	}
 }
 
-pub fn sys_event_disconnect(mut events: EventReader<DisconnectEvent>, client: Client) {
+/// Schedules a reconnect, backing off exponentially while the connection is flaky
+pub fn sys_event_disconnect(
+	mut events: EventReader<DisconnectEvent>,
+	mut state: ResMut<ConnectionState>,
+	mut ctx: ResMut<ClientContext>,
+	addr: Res<ServerAddr>,
+) {
 	for _event in events.read() {
-		if let Ok(server_address) = client.server_address() {
-			info!("Disconnected from server {}", server_address);
+		info!("Disconnected from server {}", addr.0);
+		if *state == ConnectionState::Closed {
+			continue;
 		}
+
+		let now = Instant::now();
+		if ctx.connected_at.is_some_and(|t| now - t >= STABLE_CONNECTION) {
+			ctx.retries = 0;
+		}
+		ctx.connected_at = None;
+
+		let backoff = u32::min(ctx.retries, MAX_BACKOFF_DOUBLINGS);
+		let delay = RECONNECT_DELAY * 2u32.pow(backoff);
+		ctx.retries += 1;
+
+		info!("Reconnecting in {:.1}s...", delay.as_secs_f32());
+		*state = ConnectionState::Reconnecting { retry_at: now + delay };
 	}
 }
 
@@ -119,47 +245,178 @@ pub fn sys_event_error(mut events: EventReader<ErrorEvent>) {
 pub fn sys_event_msg(
 	mut ctx: ResMut<ClientContext>,
 	mut event_sets: EventReader<MessageEvents>,
-	players: Query<Entity, With<Player>>,
+	mut q_intent: Query<&mut Intent, With<Proxy>>,
+	mut q_local: Query<(&mut PlayerId, &mut Team, &mut Skin), With<LocalPlayer>>,
 ) {
-    for events in event_sets.read() {
-		info!("Received message events!");
+	for events in event_sets.read() {
 		for msg in events.read::<CmdStreamChannel, msg::Assign>() {
-			info!("sys_event_msg:{:?}", msg);
+			info!("Assigned client id {}", msg.client_id);
+			ctx.client_id = Some(msg.client_id);
+			ctx.session = Some(msg.session);
+
+			let team = Team::from_index(msg.team);
+			for (mut id, mut t, mut skin) in &mut q_local {
+				*id = PlayerId(msg.client_id);
+				*t = team;
+				*skin = Skin::from(team.player_skin());
+			}
+		}
+		for msg in events.read::<InputReplChannel, msg::InputRepl>() {
+			for (client_id, frame) in msg.frames {
+				// ours is predicted from local input already
+				if Some(client_id) == ctx.client_id {
+					continue;
+				}
 
-			let player_ent = players.single();
-			ctx.client_entities.insert(msg.client_id, player_ent);
+				let ent = unwrap!(ctx.client_entities.get(&client_id), { continue; });
+				if let Ok(mut intent) = q_intent.get_mut(*ent) {
+					*intent = frame.to_intent();
+				}
+			}
 		}
-		for msg in events.read::<CmdStreamChannel, msg::InputRepl>() {
-			//let player_ent = ctx.client_entities[&msg.client_id];
-			//players.get(player_ent).
-			info!("sys_event_msg:{:?}", msg);
+		for msg in events.read::<CmdStreamChannel, msg::PlayerLeft>() {
+			info!("Client {} left", msg.client_id);
+			ctx.client_entities.remove(&msg.client_id);
 		}
 	}
 }
 
-pub fn sys_event_reject(mut events: EventReader<RejectEvent>, client: Client) {
-	for _event in events.read() {
-		if let Ok(server_address) = client.server_address() {
-			info!("Rejected by server {}", server_address);
+/// Surfaces naia's reason-less rejections and our own reasoned ones alike
+pub fn sys_event_reject(
+	mut client: Client,
+	mut state: ResMut<ConnectionState>,
+	mut events: EventReader<RejectEvent>,
+	mut event_sets: EventReader<MessageEvents>,
+	mut rejected: EventWriter<Rejected>,
+) {
+	let mut reasons: Vec<_> = events.read()
+		.map(|_| "connection refused".to_string())
+		.collect();
+	for events in event_sets.read() {
+		for msg in events.read::<CmdStreamChannel, msg::Reject>() {
+			reasons.push(msg.reason);
 		}
 	}
+
+	for reason in reasons {
+		info!("Rejected by server: {}", reason);
+		*state = ConnectionState::Closed;
+		if client.is_connected() {
+			client.disconnect();
+		}
+		rejected.send(Rejected { reason });
+	}
+}
+
+/// Our most recent inputs, newest first, for consecutive ticks
+#[derive(Default)]
+pub struct SentInput {
+	tick: Tick,
+	frames: VecDeque<msg::InputFrame>,
+}
+
+impl SentInput {
+	fn push(&mut self, tick: Tick, frame: msg::InputFrame) {
+		// older frames are no use to the server once ticks skip
+		if tick != self.tick.wrapping_add(1) {
+			self.frames.clear();
+		}
+
+		self.tick = tick;
+		self.frames.push_front(frame);
+		self.frames.truncate(INPUT_REDUNDANCY);
+	}
+
+	fn to_msg(&self) -> Option<msg::Input> {
+		let latest = *self.frames.front()?;
+		Some(msg::Input::new(self.tick, latest, self.frames.iter().skip(1).copied()))
+	}
 }
 
 pub fn sys_send_input(
 	mut client: Client,
-	input: Res<PlayerInput>,
-	mut ticks: EventReader<ClientTickEvent>,
+	state: Res<TickState>,
+	mut sent: Local<SentInput>,
+	mut q_player: Query<&mut Intent, With<LocalPlayer>>,
 ) {
-	for t in ticks.read() {
-		let cursor: Vec2 = Vec2::from_angle(input.face_turns * TAU);
-		let msg = msg::Input {
-			cursor_dx: cursor.x,
-			cursor_dy: cursor.y,
-			velocity_x: input.dir.x,
-			velocity_y: input.dir.y,
-			primary: input.primary,
-		};
-		//info!("sys_xmit_input {:?}: {:?}", state.cur_tick, msg);
-		client.send_tick_buffer_message::<InputSrcChannel, msg::Input>(&t.0, &msg);
+	if !client.is_connected() {
+		return;
+	}
+
+	let mut intent = unwrap!(q_player.get_single_mut().ok(), { return; });
+	let frame = msg::InputFrame::from(&*intent);
+
+	// predict with exactly what the server will see
+	*intent = frame.to_intent();
+	sent.push(state.cur_tick, frame);
+	let msg = unwrap!(sent.to_msg(), { return; });
+	//info!("sys_xmit_input {:?}: {:?}", state.cur_tick, msg);
+	client.send_message::<InputSrcChannel, msg::Input>(&msg);
+}
+
+/// Fleshes out replicated players so the simulation can drive them
+pub fn sys_proxy_add_players(
+	mut cmds: Commands,
+	mut ctx: ResMut<ClientContext>,
+	q_added: Query<(Entity, &NetPlayer, &NetPosition), Added<NetPlayer>>,
+) {
+	for (ent, player, pos) in &q_added {
+		ctx.client_entities.insert(*player.client_id, ent);
+
+		let name = format!("Player {}", *player.client_id);
+		let pos = Position::from(pos.to_vec2());
+		cmds.entity(ent).insert((
+			player_bundle(PlayerId(*player.client_id), &name, player.team(), pos),
+			Proxy,
+		));
+	}
+}
+
+pub fn sys_proxy_add_shots(
+	mut cmds: Commands,
+	q_added: Query<(Entity, &NetShot, &NetPosition, &NetVelocity), Added<NetShot>>,
+) {
+	for (ent, shot, pos, vel) in &q_added {
+		cmds.entity(ent).insert((
+			shot_bundle(shot.team(), None, pos.to_vec2(), vel.to_vec2()),
+			Proxy,
+		));
+	}
+}
+
+/// Copies replicated state onto proxies. Remote players keep moving on their
+/// last known intent (see `sys_event_msg`) between updates.
+#[allow(clippy::type_complexity)]
+pub fn sys_proxy_sync(
+	mut q_pos: Query<(&NetPosition, &mut Position), (With<Proxy>, Changed<NetPosition>)>,
+	mut q_vel: Query<(&NetVelocity, &mut Velocity), (With<Proxy>, Changed<NetVelocity>)>,
+	mut q_facing: Query<(&NetFacing, &mut Facing), (With<Proxy>, Changed<NetFacing>)>,
+	mut q_shot: Query<(&NetShot, &mut Shot), (With<Proxy>, Changed<NetShot>)>,
+) {
+	for (net, mut pos) in &mut q_pos {
+		pos.p = net.to_vec2();
+	}
+	for (net, mut vel) in &mut q_vel {
+		vel.v = net.to_vec2();
+	}
+	for (net, mut facing) in &mut q_facing {
+		facing.turns = net.turns();
+	}
+	for (net, mut shot) in &mut q_shot {
+		shot.bounces = *net.bounces;
+	}
+}
+
+/// naia despawns replicated entities non-recursively, so clean up whatever
+/// we've parented to them (sounds, debug outlines, etc.)
+pub fn sys_despawn_orphans(
+	mut cmds: Commands,
+	q_children: Query<(Entity, &Parent)>,
+	q_entities: Query<()>,
+) {
+	for (ent, parent) in &q_children {
+		if !q_entities.contains(parent.get()) {
+			cmds.entity(ent).despawn_recursive();
+		}
 	}
 }
@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use naia_bevy_client::Client;
+use std::time::Duration;
+use crate::tick_schedule::{TickConfig, TickSchedule};
+use super::peer::{tick_diff, TickState};
+
+// naia's sending tick already leaves room for jitter; this much less keeps us from overshooting it
+const SEND_MARGIN_TICKS: f32 = 1.0;
+
+// further off than this, and it's quicker to jump than to drift back
+const RESYNC_TICKS: f32 = 8.0;
+
+// how hard, and how far, the tick rate bends to close the gap
+const SKEW_PER_TICK: f32 = 0.01;
+const MAX_SKEW: f32 = 0.05;
+const SKEW_SMOOTHING: f32 = 0.1;
+
+/// Paces client ticks so the simulation stays just far enough ahead of the
+/// server for tick-buffered inputs to arrive in time, nudging the tick rate up
+/// or down instead of jumping whenever the network conditions change.
+pub struct ClockSyncPlugin;
+
+impl Plugin for ClockSyncPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(ClockSync::default())
+			.add_systems(TickSchedule::PreTicks, sys_clock_sync);
+	}
+}
+
+/// What we know of the server's clock, for pacing and diagnostics
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ClockSync {
+	/// Average round trip time, in milliseconds
+	pub rtt_ms: f32,
+	/// Round trip time variation, in milliseconds
+	pub jitter_ms: f32,
+	/// How many ticks ahead of the server we're aiming to be
+	pub target_ticks: f32,
+	/// How many ticks ahead of the server we actually are
+	pub offset_ticks: f32,
+	/// Current speed up (negative) or slow down (positive) of the tick interval
+	pub skew: f32,
+	/// Whether we've latched onto the server's clock yet
+	pub synced: bool,
+	accum: Duration,
+}
+
+impl ClockSync {
+	/// How far we are from where we want to be, in ticks; positive when ahead
+	pub fn error_ticks(&self) -> f32 {
+		self.offset_ticks - self.target_ticks
+	}
+}
+
+pub fn sys_clock_sync(
+	client: Client,
+	time: Res<Time>,
+	mut clock: ResMut<ClockSync>,
+	mut config: ResMut<TickConfig>,
+	mut state: ResMut<TickState>,
+) {
+	if !client.is_connected() {
+		if clock.synced {
+			*clock = ClockSync::default();
+			config.interval = config.step;
+			state.ticks_pending = 0;
+		}
+		return;
+	}
+
+	let server_tick = unwrap!(client.server_tick(), { return; });
+	let client_tick = unwrap!(client.client_tick(), { return; });
+	let server_frac = client.server_interpolation().unwrap_or(0.0);
+	let client_frac = client.client_interpolation().unwrap_or(0.0);
+
+	clock.rtt_ms = client.rtt();
+	clock.jitter_ms = client.jitter();
+	clock.target_ticks = tick_diff(server_tick, client_tick) as f32
+		+ client_frac - server_frac - SEND_MARGIN_TICKS;
+
+	// the tick we'd be on if everything pending ran right now
+	let interval_secs = config.interval.as_secs_f32();
+	let local_tick = state.cur_tick.wrapping_add(state.ticks_pending as u16);
+	clock.offset_ticks = tick_diff(server_tick, local_tick) as f32
+		+ clock.accum.as_secs_f32() / interval_secs - server_frac;
+
+	let error = clock.error_ticks();
+	if !clock.synced || error.abs() > RESYNC_TICKS {
+		let target = clock.target_ticks + server_frac;
+		let whole = target.floor();
+		let tick = server_tick.wrapping_add(whole as i16 as u16);
+		if clock.synced {
+			info!("Clock off by {:.1} ticks; resyncing @ tick {}", error, tick);
+		}
+
+		state.cur_tick = tick;
+		state.ticks_pending = 0;
+		clock.accum = config.step.mul_f32(target - whole);
+		clock.offset_ticks = clock.target_ticks;
+		clock.skew = 0.0;
+		clock.synced = true;
+	} else {
+		let skew = (error * SKEW_PER_TICK).clamp(-MAX_SKEW, MAX_SKEW);
+		clock.skew += (skew - clock.skew) * SKEW_SMOOTHING;
+	}
+
+	config.interval = config.step.mul_f32(1.0 + clock.skew);
+
+	clock.accum += time.delta();
+	while clock.accum >= config.interval {
+		clock.accum -= config.interval;
+		state.ticks_pending += 1;
+	}
+}
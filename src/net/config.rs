@@ -1,67 +1,280 @@
+use bevy::ecs::system::Resource;
 use naia_bevy_shared::{
 	Channel,
 	ChannelDirection,
 	LinkConditionerConfig,
+	Message,
 	Protocol,
 	ChannelMode,
-	TickBufferSettings,
 	ReliableSettings,
+	Replicate,
 };
-use std::time::Duration;
-use super::msg;
+use std::{
+	any::type_name,
+	fmt,
+	sync::OnceLock,
+	time::Duration,
+};
+use crate::args::app_version;
+use super::{msg, repl};
 
 // ~= 60fps
 pub const TICK_INTERVAL: Duration = Duration::from_nanos(16_666_667);
 
+// how many ticks of input each input message carries, to ride out lost packets
+pub const INPUT_REDUNDANCY: usize = 8;
+
+// how long either side waits to hear from the other before giving up on them
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+// bump whenever a message or component changes its fields or how they're
+// quantized; the protocol fingerprint can't see inside them
+const WIRE_REVISION: u32 = 1;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
 #[derive(Channel)]
 pub struct InputSrcChannel;
 
 #[derive(Channel)]
 pub struct CmdStreamChannel;
 
+#[derive(Channel)]
+pub struct ChatChannel;
+
+#[derive(Channel)]
+pub struct AdminChannel;
+
 #[derive(Channel)]
 pub struct EntityAssignmentChannel;
 
+#[derive(Channel)]
+pub struct StateChannel;
+
+#[derive(Channel)]
+pub struct InputReplChannel;
+
 fn protocol(link_cond: Option<LinkConditionerConfig>) -> Protocol {
-	let mut builder = Protocol::builder();
+	register(link_cond).protocol
+}
+
+/// Fingerprint of the app version, tick rate, channels, message and component
+/// types, and `WIRE_REVISION`; builds that differ in it can't play together
+pub fn protocol_version() -> u64 {
+	// never changes while running, and building a whole protocol isn't cheap
+	static VERSION: OnceLock<u64> = OnceLock::new();
+	*VERSION.get_or_init(|| register(None).hash)
+}
+
+fn register(link_cond: Option<LinkConditionerConfig>) -> Registry {
+	let mut registry = Registry::new();
 
 	if let Some(cond) = link_cond {
-		builder.link_condition(cond);
+		registry.protocol.link_condition(cond);
 	}
 
-	builder
+	registry
 		.tick_interval(TICK_INTERVAL)
-		.add_channel::<InputSrcChannel>(
+		.message::<msg::Auth>()
+		.message::<msg::Reject>()
+		.channel::<InputSrcChannel>(
 			ChannelDirection::ClientToServer,
-			ChannelMode::TickBuffered(TickBufferSettings::default()),
+			ChannelMode::UnorderedUnreliable,
 		)
-		.add_channel::<CmdStreamChannel>(
+		.channel::<CmdStreamChannel>(
 			ChannelDirection::ServerToClient,
 			ChannelMode::OrderedReliable(ReliableSettings::default())
 		)
-		.add_channel::<EntityAssignmentChannel>(
+		.channel::<AdminChannel>(
+			ChannelDirection::ClientToServer,
+			ChannelMode::OrderedReliable(ReliableSettings::default()),
+		)
+		.channel::<ChatChannel>(
+			ChannelDirection::ClientToServer,
+			ChannelMode::OrderedReliable(ReliableSettings::default()),
+		)
+		.channel::<EntityAssignmentChannel>(
 			ChannelDirection::ServerToClient,
 			ChannelMode::UnorderedReliable(ReliableSettings::default()),
 		)
-		.add_message::<msg::Assign>()
-		.add_message::<msg::Auth>()
-		.add_message::<msg::Input>()
-		.add_message::<msg::InputRepl>()
+		.channel::<StateChannel>(
+			ChannelDirection::ServerToClient,
+			ChannelMode::SequencedUnreliable,
+		)
+		// only ever steers proxies until the next state update, so a lost one
+		// hardly matters, and a late one would only be wrong
+		.channel::<InputReplChannel>(
+			ChannelDirection::ServerToClient,
+			ChannelMode::SequencedUnreliable,
+		)
+		.message::<msg::AdminCommand>()
+		.message::<msg::AdminReply>()
+		.message::<msg::Assign>()
+		.message::<msg::ChatBroadcast>()
+		.message::<msg::ChatSend>()
+		.message::<msg::Input>()
+		.message::<msg::InputRepl>()
+		.message::<msg::PlayerLeft>()
+		.message::<msg::PlayerState>()
+		.message::<msg::Shutdown>()
+		.component::<repl::NetFacing>()
+		.component::<repl::NetPlayer>()
+		.component::<repl::NetPosition>()
+		.component::<repl::NetShot>()
+		.component::<repl::NetVelocity>()
 		.build()
 }
 
-pub fn perfect() -> Protocol {
-	protocol(None)
+/// Builds the naia `Protocol`, hashing what goes into it along the way
+struct Registry {
+	protocol: Protocol,
+	hash: u64,
 }
 
-pub fn wifi() -> Protocol {
-	protocol(Some(LinkConditionerConfig::good_condition()))
+impl Registry {
+	fn new() -> Self {
+		let mut registry = Registry { protocol: Protocol::builder(), hash: FNV_OFFSET };
+		registry.mix(app_version().as_bytes());
+		registry.mix(&WIRE_REVISION.to_le_bytes());
+		registry
+	}
+
+	// FNV-1a, since std's hashers may change between compiler releases
+	fn mix(&mut self, bytes: &[u8]) {
+		for b in bytes.iter().chain(&[0]) {
+			self.hash ^= *b as u64;
+			self.hash = self.hash.wrapping_mul(FNV_PRIME);
+		}
+	}
+
+	fn tick_interval(&mut self, interval: Duration) -> &mut Self {
+		self.mix(&interval.as_nanos().to_le_bytes());
+		self.protocol.tick_interval(interval);
+		self
+	}
+
+	fn channel<C: Channel>(&mut self, direction: ChannelDirection, mode: ChannelMode) -> &mut Self {
+		self.mix(type_name::<C>().as_bytes());
+		self.mix(match direction {
+			ChannelDirection::ClientToServer => "client to server",
+			ChannelDirection::ServerToClient => "server to client",
+			ChannelDirection::Bidirectional => "bidirectional",
+		}.as_bytes());
+		self.mix(match mode {
+			ChannelMode::UnorderedUnreliable => "unordered unreliable",
+			ChannelMode::SequencedUnreliable => "sequenced unreliable",
+			ChannelMode::UnorderedReliable(_) => "unordered reliable",
+			ChannelMode::SequencedReliable(_) => "sequenced reliable",
+			ChannelMode::OrderedReliable(_) => "ordered reliable",
+			ChannelMode::TickBuffered(_) => "tick buffered",
+		}.as_bytes());
+		self.protocol.add_channel::<C>(direction, mode);
+		self
+	}
+
+	fn message<M: Message>(&mut self) -> &mut Self {
+		self.mix(type_name::<M>().as_bytes());
+		self.protocol.add_message::<M>();
+		self
+	}
+
+	fn component<C: Replicate>(&mut self) -> &mut Self {
+		self.mix(type_name::<C>().as_bytes());
+		self.protocol.add_component::<C>();
+		self
+	}
+
+	fn build(&mut self) -> Self {
+		Registry { protocol: self.protocol.build(), hash: self.hash }
+	}
+}
+
+/// How far behind the server remote entities are rendered, and how long they may
+/// be extrapolated past the newest snapshot when updates go missing
+#[derive(Clone, Copy, Debug, Resource)]
+pub struct Interpolation {
+	pub delay_ticks: u16,
+	pub max_extrapolate_ticks: u16,
+}
+
+/// Network conditions to simulate, and the settings tuned to cope with them
+#[derive(Clone, Resource)]
+pub struct Link {
+	pub conditioner: Option<LinkConditionerConfig>,
+	pub interp: Interpolation,
+}
+
+impl Link {
+	pub fn protocol(&self) -> Protocol {
+		protocol(self.conditioner.clone())
+	}
+
+	/// Looks up a preset by name, as given on the command line
+	pub fn preset(name: &str) -> Option<Link> {
+		match name {
+			"perfect" => Some(perfect()),
+			"wifi" => Some(wifi()),
+			"avg" => Some(global_avg()),
+			"poor" => Some(global_poor()),
+			_ => None,
+		}
+	}
+}
+
+impl Default for Link {
+	fn default() -> Self {
+		perfect()
+	}
+}
+
+impl fmt::Debug for Link {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut s = f.debug_struct("Link");
+		if let Some(cond) = &self.conditioner {
+			s.field("latency_ms", &cond.incoming_latency)
+				.field("jitter_ms", &cond.incoming_jitter)
+				.field("loss", &cond.incoming_loss);
+		}
+		s.field("interp", &self.interp).finish()
+	}
+}
+
+pub fn perfect() -> Link {
+	Link {
+		conditioner: None,
+		interp: Interpolation { delay_ticks: 2, max_extrapolate_ticks: 2 },
+	}
+}
+
+pub fn wifi() -> Link {
+	Link {
+		conditioner: Some(LinkConditionerConfig::good_condition()),
+		interp: Interpolation { delay_ticks: 3, max_extrapolate_ticks: 4 },
+	}
+}
+
+pub fn global_avg() -> Link {
+	Link {
+		conditioner: Some(LinkConditionerConfig::average_condition()),
+		interp: Interpolation { delay_ticks: 6, max_extrapolate_ticks: 6 },
+	}
 }
 
-pub fn global_avg() -> Protocol {
-	protocol(Some(LinkConditionerConfig::average_condition()))
+pub fn global_poor() -> Link {
+	Link {
+		conditioner: Some(LinkConditionerConfig::poor_condition()),
+		interp: Interpolation { delay_ticks: 10, max_extrapolate_ticks: 8 },
+	}
 }
 
-pub fn global_poor() -> Protocol {
-	protocol(Some(LinkConditionerConfig::poor_condition()))
+/// Arbitrary conditions, with interpolation tuned to ride out the given jitter
+pub fn custom(latency_ms: u32, jitter_ms: u32, loss: f32) -> Link {
+	let tick_ms = TICK_INTERVAL.as_secs_f32() * 1000.0;
+	let delay_ticks = 2 + (1.5 * jitter_ms as f32 / tick_ms).ceil() as u16;
+
+	Link {
+		conditioner: Some(LinkConditionerConfig::new(latency_ms, jitter_ms, loss)),
+		interp: Interpolation { delay_ticks, max_extrapolate_ticks: delay_ticks.max(4) },
+	}
 }
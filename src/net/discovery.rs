@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+use std::{
+	io,
+	net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+	time::{Duration, Instant},
+};
+use crate::args::{app_version, DEFAULT_PORT};
+use super::{
+	config::protocol_version,
+	server::ListenAddr,
+	session::Sessions,
+};
+
+/// Where servers listen for LAN queries, whatever port they play on
+pub const DISCOVERY_PORT: u16 = DEFAULT_PORT + 1;
+
+const QUERY: &[u8] = b"shooter?";
+const REPLY: &[u8] = b"shooter!";
+
+// queries are padded out to this, and replies never exceed it, so nobody gets
+// more out of us than they sent in
+const QUERY_LEN: usize = 512;
+
+// how often clients ask around, and how long until a quiet server drops off the list
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers LAN queries about this server, unless it's only reachable locally
+pub struct DiscoveryServerPlugin;
+
+impl Plugin for DiscoveryServerPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<ServerInfo>()
+			.add_systems(Startup, sys_start_responder)
+			.add_systems(Update, sys_respond.run_if(resource_exists::<Responder>()));
+	}
+}
+
+/// Keeps `LanServers` up to date with whatever servers answer on the LAN
+pub struct LanScanPlugin;
+
+impl Plugin for LanScanPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<LanServers>()
+			.add_systems(Startup, sys_start_scanner)
+			.add_systems(Update, sys_scan.run_if(resource_exists::<Scanner>()));
+	}
+}
+
+/// How the server describes itself to anyone looking
+#[derive(Clone, Debug, Resource)]
+pub struct ServerInfo {
+	pub name: String,
+	pub map: String,
+}
+
+impl Default for ServerInfo {
+	fn default() -> Self {
+		ServerInfo { name: "shooter".into(), map: "default".into() }
+	}
+}
+
+/// A server that answered on the LAN
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanServer {
+	/// Where to connect to play
+	pub addr: SocketAddr,
+	pub version: String,
+	pub protocol: u64,
+	pub name: String,
+	pub map: String,
+	pub players: usize,
+	pub max_players: usize,
+}
+
+impl LanServer {
+	/// Whether it speaks the same protocol we do, so we could join it
+	pub fn is_compatible(&self) -> bool {
+		self.protocol == protocol_version()
+	}
+
+	// plain text, one field per line
+	fn encode(&self) -> Vec<u8> {
+		let text = format!(
+			"{}\n{:016x}\n{}\n{}\n{}\n{}\n{}",
+			self.version,
+			self.protocol,
+			one_line(&self.name),
+			one_line(&self.map),
+			self.players,
+			self.max_players,
+			self.addr.port(),
+		);
+		[REPLY, text.as_bytes()].concat()
+	}
+
+	fn decode(from: SocketAddr, bytes: &[u8]) -> Option<Self> {
+		let text = std::str::from_utf8(bytes.strip_prefix(REPLY)?).ok()?;
+		let mut fields = text.split('\n');
+		let mut next = || fields.next();
+
+		Some(LanServer {
+			version: next()?.into(),
+			protocol: u64::from_str_radix(next()?, 16).ok()?,
+			name: next()?.into(),
+			map: next()?.into(),
+			players: next()?.parse().ok()?,
+			max_players: next()?.parse().ok()?,
+			addr: SocketAddr::new(from.ip(), next()?.parse().ok()?),
+		})
+	}
+}
+
+// names are whatever the host typed, so they mustn't be able to spill into
+// the next field
+fn one_line(text: &str) -> String {
+	text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+/// Servers heard from recently, in the order they first answered
+#[derive(Debug, Default, Resource)]
+pub struct LanServers {
+	pub servers: Vec<(LanServer, Instant)>,
+}
+
+impl LanServers {
+	fn update(&mut self, server: LanServer, now: Instant) {
+		match self.servers.iter_mut().find(|(s, _)| s.addr == server.addr) {
+			Some(entry) => *entry = (server, now),
+			None => {
+				info!(
+					"Found {} on the LAN at {}: {}, {}/{} players",
+					server.name, server.addr, server.map, server.players, server.max_players,
+				);
+				self.servers.push((server, now));
+			},
+		}
+	}
+}
+
+#[derive(Resource)]
+struct Responder(UdpSocket);
+
+#[derive(Resource)]
+struct Scanner {
+	sock: UdpSocket,
+	last_query: Option<Instant>,
+}
+
+/// Whether `ip` could be on the same LAN; nobody else gets an answer
+fn is_lan(ip: IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.is_loopback(),
+		IpAddr::V6(ip) => ip.is_unique_local() || ip.is_unicast_link_local() || ip.is_loopback(),
+	}
+}
+
+fn bind_nonblocking(addr: SocketAddr) -> io::Result<UdpSocket> {
+	let sock = UdpSocket::bind(addr)?;
+	sock.set_nonblocking(true)?;
+	Ok(sock)
+}
+
+fn sys_start_responder(mut cmds: Commands, listen: Res<ListenAddr>) {
+	if listen.0.ip().is_loopback() {
+		return;
+	}
+
+	let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DISCOVERY_PORT);
+	match bind_nonblocking(addr) {
+		Ok(sock) => cmds.insert_resource(Responder(sock)),
+		// e.g. another server on this machine got there first
+		Err(e) => println!("Not answering LAN queries on {}: {}", addr, e),
+	}
+}
+
+fn sys_respond(
+	responder: Res<Responder>,
+	info: Res<ServerInfo>,
+	listen: Res<ListenAddr>,
+	sessions: Res<Sessions>,
+) {
+	let mut buf = [0; QUERY_LEN];
+	while let Ok((len, from)) = responder.0.recv_from(&mut buf) {
+		if len < QUERY_LEN || !buf.starts_with(QUERY) || !is_lan(from.ip()) {
+			continue;
+		}
+
+		let reply = LanServer {
+			addr: listen.0,
+			version: app_version().into(),
+			protocol: protocol_version(),
+			name: info.name.clone(),
+			map: info.map.clone(),
+			players: sessions.connected().count(),
+			max_players: sessions.max_players,
+		};
+		let reply = reply.encode();
+		if reply.len() > len {
+			// only with an absurdly long server name
+			continue;
+		}
+		// best effort; they'll ask again
+		let _ = responder.0.send_to(&reply, from);
+	}
+}
+
+fn sys_start_scanner(mut cmds: Commands) {
+	let sock = bind_nonblocking(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+		.and_then(|sock| sock.set_broadcast(true).map(|_| sock));
+	match sock {
+		Ok(sock) => cmds.insert_resource(Scanner { sock, last_query: None }),
+		Err(e) => warn!("Can't scan the LAN for servers: {}", e),
+	}
+}
+
+fn sys_scan(mut scanner: ResMut<Scanner>, mut lan: ResMut<LanServers>) {
+	let now = Instant::now();
+	if scanner.last_query.is_none_or(|t| now - t >= SCAN_INTERVAL) {
+		scanner.last_query = Some(now);
+		let to = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), DISCOVERY_PORT);
+		let mut query = QUERY.to_vec();
+		query.resize(QUERY_LEN, 0);
+		if let Err(e) = scanner.sock.send_to(&query, to) {
+			debug!("LAN query failed: {}", e);
+		}
+	}
+
+	let mut buf = [0; QUERY_LEN];
+	while let Ok((len, from)) = scanner.sock.recv_from(&mut buf) {
+		if let Some(server) = LanServer::decode(from, &buf[..len]) {
+			lan.update(server, now);
+		}
+	}
+
+	lan.servers.retain(|(_, seen)| now - *seen < SERVER_TIMEOUT);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn answers_only_the_lan() {
+		for ip in ["192.168.1.2", "10.0.0.1", "172.16.5.5", "169.254.1.1", "127.0.0.1", "fd00::1", "fe80::1", "::1"] {
+			assert!(is_lan(ip.parse().unwrap()), "{}", ip);
+		}
+		for ip in ["8.8.8.8", "1.1.1.1", "172.32.0.1", "2001:db8::1"] {
+			assert!(!is_lan(ip.parse().unwrap()), "{}", ip);
+		}
+	}
+
+	fn server() -> LanServer {
+		LanServer {
+			addr: "192.168.1.2:5323".parse().unwrap(),
+			version: app_version().into(),
+			protocol: protocol_version(),
+			name: "shooter".into(),
+			map: "default".into(),
+			players: 3,
+			max_players: 16,
+		}
+	}
+
+	#[test]
+	fn reply_fits_in_query() {
+		let server = LanServer { name: "x".repeat(256), ..server() };
+		assert!(server.encode().len() <= QUERY_LEN);
+	}
+
+	#[test]
+	fn round_trips() {
+		let server = server();
+		let decoded = LanServer::decode(server.addr, &server.encode());
+		assert_eq!(decoded, Some(server));
+	}
+
+	#[test]
+	fn names_stay_on_one_line() {
+		let server = LanServer { name: "two\nlines\r\t".into(), ..server() };
+		let decoded = LanServer::decode(server.addr, &server.encode()).unwrap();
+		assert_eq!(decoded, LanServer { name: "two lines  ".into(), ..server });
+	}
+}
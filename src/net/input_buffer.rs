@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+use naia_bevy_shared::{sequence_greater_than, Tick};
+use std::collections::VecDeque;
+use super::msg::InputFrame;
+
+// ~2s @ 60 ticks/s; nobody legitimately runs this far ahead of us
+const MAX_AHEAD: u16 = 120;
+
+/// A player's inputs, by the tick they're meant for, waiting on the simulation
+/// to catch up to them
+#[derive(Component, Default)]
+pub struct InputBuffer {
+	/// Oldest first, at most one per tick
+	frames: VecDeque<(Tick, InputFrame)>,
+	/// Newest tick already simulated
+	consumed: Option<Tick>,
+	/// Newest tick received input for
+	newest: Option<Tick>,
+	/// Most recent input actually received
+	last: Option<InputFrame>,
+}
+
+/// What became of a received input frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Received {
+	Fresh,
+	Duplicate,
+	/// Its tick has already been simulated, or is too far off to be trusted
+	Late,
+}
+
+impl InputBuffer {
+	pub fn insert(&mut self, tick: Tick, frame: InputFrame) -> Received {
+		if let Some(consumed) = self.consumed {
+			if !sequence_greater_than(tick, consumed)
+				|| sequence_greater_than(tick, consumed.wrapping_add(MAX_AHEAD)) {
+				return Received::Late;
+			}
+		}
+
+		let at = self.frames.iter()
+			.position(|(t, _)| !sequence_greater_than(tick, *t))
+			.unwrap_or(self.frames.len());
+		if self.frames.get(at).is_some_and(|(t, _)| *t == tick) {
+			return Received::Duplicate;
+		}
+
+		self.frames.insert(at, (tick, frame));
+		if self.newest.is_none_or(|newest| sequence_greater_than(tick, newest)) {
+			self.newest = Some(tick);
+		}
+		Received::Fresh
+	}
+
+	/// Newest tick received input for, simulated or not
+	pub fn newest(&self) -> Option<Tick> {
+		self.newest
+	}
+
+	/// Input for `tick`, if it arrived, dropping it and anything older
+	pub fn take(&mut self, tick: Tick) -> Option<InputFrame> {
+		self.consumed = Some(tick);
+
+		let mut found = None;
+		while let Some((t, frame)) = self.frames.front() {
+			if sequence_greater_than(*t, tick) {
+				break;
+			}
+			if *t == tick {
+				found = Some(*frame);
+			}
+			self.frames.pop_front();
+		}
+
+		if found.is_some() {
+			self.last = found;
+		}
+		found
+	}
+
+	/// Whether we've ever heard from this player, and so have something to replay
+	pub fn has_input(&self) -> bool {
+		self.last.is_some()
+	}
+}
+
+/// How well player input is surviving the trip to the server, for diagnostics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource)]
+pub struct InputStats {
+	/// Inputs whose own packet was lost, but which a later packet carried anyway
+	pub recovered: u64,
+	/// Inputs that only showed up after their tick was simulated
+	pub late: u64,
+	/// Ticks simulated without input, repeating the previous input instead
+	pub missing: u64,
+}
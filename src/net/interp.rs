@@ -0,0 +1,172 @@
+use bevy::prelude::*;
+use naia_bevy_client::{
+	Client,
+	events::UpdateComponentEvents,
+};
+use naia_bevy_shared::{ReceiveEvents, Tick};
+use std::collections::{HashMap, VecDeque};
+use crate::{
+	movement::{Facing, sys_write_back},
+	sim::Proxy,
+	tick_schedule::{TickConfig, TickSchedule},
+};
+use super::{
+	config::Interpolation,
+	peer::tick_diff,
+	repl::{NetFacing, NetPosition, NetVelocity},
+};
+
+// plenty for any sane delay; older snapshots are pruned as render time passes them
+const BUFFER_LEN: usize = 32;
+
+/// Renders proxies slightly in the past, smoothly interpolated between the two
+/// server snapshots bracketing the render time. The simulated `Position` is
+/// left alone; only the `Transform` is affected.
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.add_systems(TickSchedule::Network, sys_snapshot_record.in_set(ReceiveEvents))
+			.add_systems(TickSchedule::PreTicks, sys_snapshot_add)
+			.add_systems(TickSchedule::PostTicks, sys_interpolate.after(sys_write_back));
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Snapshot {
+	tick: Tick,
+	pos: Vec2,
+	vel: Vec2,
+	face_turns: f32,
+}
+
+#[derive(Component, Default)]
+pub struct SnapshotBuffer {
+	snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+	fn push(&mut self, snapshot: Snapshot) {
+		if let Some(last) = self.snapshots.back_mut() {
+			if last.tick == snapshot.tick {
+				*last = snapshot;
+				return;
+			}
+			if tick_diff(last.tick, snapshot.tick) < 0 {
+				// out of order; too late to be of use
+				return;
+			}
+		}
+
+		if self.snapshots.len() >= BUFFER_LEN {
+			self.snapshots.pop_front();
+		}
+		self.snapshots.push_back(snapshot);
+	}
+
+	/// Pose at `offset` ticks after `base`, if we have anything to go on
+	fn sample(&mut self, base: Tick, offset: f32, interp: &Interpolation, tick_secs: f32) -> Option<(Vec2, f32)> {
+		let at = |s: &Snapshot| tick_diff(base, s.tick) as f32;
+
+		// forget everything but the newest snapshot at or before render time
+		while self.snapshots.len() >= 2 && at(&self.snapshots[1]) <= offset {
+			self.snapshots.pop_front();
+		}
+
+		let s0 = *self.snapshots.front()?;
+		let t0 = at(&s0);
+
+		if offset <= t0 {
+			// render time is before anything we know about
+			return Some((s0.pos, s0.face_turns));
+		}
+
+		if let Some(s1) = self.snapshots.get(1) {
+			let t1 = at(s1);
+			let alpha = (offset - t0) / (t1 - t0);
+			let pos = s0.pos.lerp(s1.pos, alpha);
+			let face_turns = s0.face_turns + alpha * turns_between(s0.face_turns, s1.face_turns);
+			return Some((pos, face_turns));
+		}
+
+		// ran out of snapshots; dead reckon for a little while, then hold still
+		let ahead = f32::min(offset - t0, interp.max_extrapolate_ticks as f32);
+		Some((s0.pos + s0.vel * ahead * tick_secs, s0.face_turns))
+	}
+}
+
+/// Shortest signed rotation from `a` to `b`, in turns
+fn turns_between(a: f32, b: f32) -> f32 {
+	(b - a + 0.5).rem_euclid(1.0) - 0.5
+}
+
+fn snapshot_of(tick: Tick, pos: &NetPosition, vel: Option<&NetVelocity>, facing: Option<&NetFacing>) -> Snapshot {
+	Snapshot {
+		tick,
+		pos: pos.to_vec2(),
+		vel: vel.map_or(Vec2::ZERO, |v| v.to_vec2()),
+		face_turns: facing.map_or(0.0, |f| f.turns()),
+	}
+}
+
+#[allow(clippy::type_complexity)]
+fn sys_snapshot_add(
+	mut cmds: Commands,
+	client: Client,
+	q_added: Query<(Entity, &NetPosition, Option<&NetVelocity>, Option<&NetFacing>), Added<Proxy>>,
+) {
+	let tick = unwrap!(client.server_tick(), { return; });
+
+	for (ent, pos, vel, facing) in &q_added {
+		let mut buffer = SnapshotBuffer::default();
+		buffer.push(snapshot_of(tick, pos, vel, facing));
+		cmds.entity(ent).insert(buffer);
+	}
+}
+
+fn sys_snapshot_record(
+	mut event_sets: EventReader<UpdateComponentEvents>,
+	mut q_proxies: Query<(&mut SnapshotBuffer, &NetPosition, Option<&NetVelocity>, Option<&NetFacing>)>,
+) {
+	// several components of the same entity may update on the same tick
+	let mut updated: HashMap<Entity, Tick> = HashMap::new();
+	for events in event_sets.read() {
+		let pos = events.read::<NetPosition>();
+		let vel = events.read::<NetVelocity>();
+		let facing = events.read::<NetFacing>();
+		for (tick, ent) in pos.into_iter().chain(vel).chain(facing) {
+			updated.entry(ent)
+				.and_modify(|t| if tick_diff(*t, tick) > 0 { *t = tick })
+				.or_insert(tick);
+		}
+	}
+
+	for (ent, tick) in updated {
+		if let Ok((mut buffer, pos, vel, facing)) = q_proxies.get_mut(ent) {
+			buffer.push(snapshot_of(tick, pos, vel, facing));
+		}
+	}
+}
+
+pub fn sys_interpolate(
+	client: Client,
+	interp: Res<Interpolation>,
+	tick: Res<TickConfig>,
+	mut q_proxies: Query<(&mut SnapshotBuffer, &mut Transform, Option<&Facing>)>,
+) {
+	let server_tick = unwrap!(client.server_tick(), { return; });
+	let offset = client.server_interpolation().unwrap_or(0.0);
+	let base = server_tick.wrapping_sub(interp.delay_ticks);
+	let tick_secs = tick.step.as_secs_f32();
+
+	for (mut buffer, mut t, facing) in &mut q_proxies {
+		let (pos, face_turns) = unwrap!(buffer.sample(base, offset, &interp, tick_secs), { continue; });
+
+		t.translation.x = pos.x;
+		t.translation.y = pos.y;
+		if facing.is_some() {
+			t.rotation = Facing { turns: face_turns }.to_quat();
+		}
+	}
+}
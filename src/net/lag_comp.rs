@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use naia_bevy_shared::Tick;
+use parry2d::shape::SharedShape;
+use std::{
+	collections::VecDeque,
+	time::Duration,
+};
+use crate::{
+	collide::{Collidable, PosedShapes},
+	movement::Position,
+	player::Player,
+	sim::{self, Targets},
+	tick_schedule::{TickConfig, TickSchedule},
+};
+use super::{
+	config::Interpolation,
+	peer::{tick_diff, TickState},
+	session::Players,
+};
+
+// ~1s @ 60 ticks/s; comfortably more than we'll ever rewind
+const HISTORY_LEN: usize = 64;
+
+// past this, the shooter is lagging too badly to be worth favoring over the target
+const MAX_REWIND: Duration = Duration::from_millis(200);
+
+/// Tests shots against players where the shooter saw them, rather than where
+/// they are now, so shooters don't have to lead their targets by their latency.
+pub struct LagCompPlugin;
+
+impl Plugin for LagCompPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(PoseHistory::default())
+			.add_systems(TickSchedule::Tick, (
+				sys_rewind_targets
+					.after(sim::sys_index_targets)
+					.before(sim::sys_move_shots),
+				sys_record_poses.after(sim::sys_move_player),
+			));
+	}
+}
+
+struct Frame {
+	tick: Tick,
+	poses: Vec<(Entity, SharedShape, Position)>,
+}
+
+/// Where every player was at the end of each recent tick
+#[derive(Default, Resource)]
+pub struct PoseHistory {
+	frames: VecDeque<Frame>,
+}
+
+impl PoseHistory {
+	fn record(&mut self, frame: Frame) {
+		if self.frames.len() >= HISTORY_LEN {
+			self.frames.pop_front();
+		}
+		self.frames.push_back(frame);
+	}
+
+	/// Newest frame at or before `tick`, or the oldest we have if it's older still
+	fn at(&self, tick: Tick) -> Option<&Frame> {
+		self.frames.iter()
+			.rev()
+			.find(|f| tick_diff(f.tick, tick) >= 0)
+			.or(self.frames.front())
+	}
+}
+
+/// How many ticks behind the server a player was looking when they pulled the trigger
+fn rewind_ticks(rtt_ms: Option<f32>, interp: &Interpolation, tick: &TickConfig) -> u16 {
+	let tick_ms = tick.step.as_secs_f32() * 1000.0;
+	let rtt_ticks = rtt_ms.map_or(0.0, |ms| ms / tick_ms).round() as u16;
+	let max_ticks = (MAX_REWIND.as_secs_f32() * 1000.0 / tick_ms) as u16;
+
+	u16::min(rtt_ticks + interp.delay_ticks, max_ticks)
+}
+
+fn sys_record_poses(
+	state: Res<TickState>,
+	mut history: ResMut<PoseHistory>,
+	q_players: Query<(Entity, &Collidable, &Position), With<Player>>,
+) {
+	history.record(Frame {
+		tick: state.cur_tick,
+		poses: q_players.iter()
+			.map(|(ent, col, pos)| (ent, col.shape.clone(), pos.clone()))
+			.collect(),
+	});
+}
+
+/// Has each shooter's shots test against the players they saw, rather than the
+/// present ones the simulation would otherwise use
+fn sys_rewind_targets(
+	players: Players,
+	state: Res<TickState>,
+	interp: Res<Interpolation>,
+	tick: Res<TickConfig>,
+	history: Res<PoseHistory>,
+	mut targets: ResMut<Targets>,
+	q_players: Query<(), With<Player>>,
+) {
+	for player in players.iter() {
+		let shooter = player.ent;
+		let rewind = rewind_ticks(player.rtt_ms, &interp, &tick);
+		let frame = unwrap!(history.at(state.cur_tick.wrapping_sub(rewind)), { continue; });
+
+		let seen = frame.poses.iter()
+			.filter(|(ent, _, _)| *ent != shooter && q_players.contains(*ent))
+			.cloned();
+		targets.set_view(shooter, PosedShapes::new(seen));
+	}
+}
@@ -1,6 +1,19 @@
+pub mod admin;
 pub mod client;
+pub mod clock;
+pub mod auth;
+pub mod chat;
 pub mod config;
+pub mod discovery;
+pub mod interp;
+pub mod lag_comp;
+pub mod peer;
+pub mod predict;
 pub mod server;
+pub mod session;
+pub mod stats;
 
+mod input_buffer;
 mod msg;
-mod peer;
+mod quant;
+mod repl;
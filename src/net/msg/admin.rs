@@ -0,0 +1,14 @@
+use naia_bevy_shared::Message;
+
+/// A console command, from someone who says they know the admin password
+#[derive(Message)]
+pub struct AdminCommand {
+	pub password: String,
+	pub command: String,
+}
+
+/// One line of what came of an `AdminCommand`
+#[derive(Debug, Message)]
+pub struct AdminReply {
+	pub text: String,
+}
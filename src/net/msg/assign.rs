@@ -3,4 +3,8 @@ use naia_bevy_shared::Message;
 #[derive(Debug, Message)]
 pub struct Assign {
 	pub client_id: u32,
+	/// Index of the player's `Team`
+	pub team: u8,
+	/// Presented when reconnecting, to resume as the same player
+	pub session: u64,
 }
@@ -1,12 +1,36 @@
 use naia_bevy_shared::Message;
+use crate::{args::app_version, net::config::protocol_version};
 
 #[derive(Message)]
 pub struct Auth {
+	pub protocol: u64,
+	pub version: String,
 	pub token: String,
+	pub name: String,
+	/// From a previous `Assign`, when reconnecting
+	pub session: Option<u64>,
 }
 
 impl Auth {
-	pub fn new(token: &str) -> Self {
-		Auth{ token: token.to_string() }
+	pub fn new(token: &str, name: &str, session: Option<u64>) -> Self {
+		Auth{
+			protocol: protocol_version(),
+			version: app_version().to_string(),
+			token: token.to_string(),
+			name: name.to_string(),
+			session,
+		}
+	}
+}
+
+/// Why the server turned us away, sent in place of everything else
+#[derive(Message)]
+pub struct Reject {
+	pub reason: String,
+}
+
+impl Reject {
+	pub fn new(reason: &str) -> Self {
+		Reject{ reason: reason.to_string() }
 	}
 }
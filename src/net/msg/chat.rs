@@ -0,0 +1,18 @@
+use naia_bevy_shared::Message;
+
+/// Something a player wants to say
+#[derive(Debug, Message)]
+pub struct ChatSend {
+	pub text: String,
+	/// Only for the sender's teammates
+	pub team_only: bool,
+}
+
+/// Something a player said, relayed by the server to whoever should hear it
+#[derive(Debug, Message)]
+pub struct ChatBroadcast {
+	pub client_id: u32,
+	pub name: String,
+	pub text: String,
+	pub team_only: bool,
+}
@@ -1,33 +1,104 @@
-use naia_bevy_shared::Message;
+use naia_bevy_shared::{Message, Serde, Tick};
+use std::iter;
+use crate::player::Intent;
+use crate::net::quant::{QuantAngle, QuantDir};
 
-#[derive(Debug, Message)]
-pub struct Input {
-	pub velocity_x: f32,
-	pub velocity_y: f32,
-	pub cursor_dx: f32,
-	pub cursor_dy: f32,
+/// One tick's worth of player input
+#[derive(Clone, Copy, Debug, PartialEq, Serde)]
+pub struct InputFrame {
+	pub dir: QuantDir,
+	pub facing: QuantAngle,
 	pub primary: bool,
 }
 
-#[derive(Debug, Message)]
-pub struct InputRepl {
-	pub client_id: u32,
-	pub velocity_x: f32,
-	pub velocity_y: f32,
-	pub cursor_dx: f32,
-	pub cursor_dy: f32,
-	pub primary: bool,
+impl InputFrame {
+	pub fn to_intent(self) -> Intent {
+		Intent {
+			dir: self.dir.get(),
+			face_turns: self.facing.get(),
+			primary: self.primary,
+		}
+	}
 }
 
-impl InputRepl {
-	pub fn new(client_id: u32, input: &Input) -> Self {
+impl From<&Intent> for InputFrame {
+	fn from(intent: &Intent) -> Self {
 		Self {
-			client_id,
-			cursor_dx: input.cursor_dx,
-			cursor_dy: input.cursor_dy,
-			velocity_x: input.velocity_x,
-			velocity_y: input.velocity_y,
-			primary: input.primary,
+			dir: QuantDir::new(intent.dir),
+			facing: QuantAngle::new(intent.face_turns),
+			primary: intent.primary,
 		}
 	}
 }
+
+fn changed<T: PartialEq>(newer: T, older: T) -> Option<T> {
+	if newer != older { Some(older) } else { None }
+}
+
+/// An input frame, as far as it differs from the frame the tick after it
+#[derive(Clone, Debug, PartialEq, Serde)]
+struct InputDelta {
+	dir: Option<QuantDir>,
+	facing: Option<QuantAngle>,
+	primary: Option<bool>,
+}
+
+impl InputDelta {
+	fn between(newer: &InputFrame, older: &InputFrame) -> Self {
+		Self {
+			dir: changed(newer.dir, older.dir),
+			facing: changed(newer.facing, older.facing),
+			primary: changed(newer.primary, older.primary),
+		}
+	}
+
+	fn apply(&self, newer: &InputFrame) -> InputFrame {
+		InputFrame {
+			dir: self.dir.unwrap_or(newer.dir),
+			facing: self.facing.unwrap_or(newer.facing),
+			primary: self.primary.unwrap_or(newer.primary),
+		}
+	}
+}
+
+/// Input for `tick`, plus the ticks before it again, so a lost packet or two
+/// doesn't cost the server any input
+#[derive(Debug, Message)]
+pub struct Input {
+	pub tick: Tick,
+	latest: InputFrame,
+	/// Newest first, each relative to the frame before it in the list
+	history: Vec<InputDelta>,
+}
+
+impl Input {
+	/// `older` runs back in time from the tick before `tick`
+	pub fn new(tick: Tick, latest: InputFrame, older: impl IntoIterator<Item = InputFrame>) -> Self {
+		let mut newer = latest;
+		let history = older.into_iter()
+			.map(|frame| {
+				let delta = InputDelta::between(&newer, &frame);
+				newer = frame;
+				delta
+			})
+			.collect();
+
+		Self { tick, latest, history }
+	}
+
+	/// Every frame carried, newest first, along with its tick
+	pub fn frames(&self) -> impl Iterator<Item = (Tick, InputFrame)> + '_ {
+		let (mut tick, mut frame) = (self.tick, self.latest);
+		iter::once((tick, frame)).chain(self.history.iter().map(move |delta| {
+			tick = tick.wrapping_sub(1);
+			frame = delta.apply(&frame);
+			(tick, frame)
+		}))
+	}
+}
+
+/// Everyone's input for a tick, by client id
+#[derive(Debug, Message)]
+pub struct InputRepl {
+	pub frames: Vec<(u32, InputFrame)>,
+}
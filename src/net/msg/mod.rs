@@ -1,8 +1,23 @@
+mod admin;
+pub use admin::*;
+
 mod assign;
 pub use assign::*;
 
 mod auth;
 pub use auth::*;
 
+mod chat;
+pub use chat::*;
+
 mod input;
 pub use input::*;
+
+mod player_left;
+pub use player_left::*;
+
+mod shutdown;
+pub use shutdown::*;
+
+mod state;
+pub use state::*;
@@ -0,0 +1,7 @@
+use naia_bevy_shared::Message;
+
+/// A player is gone for good, and won't be resuming their session
+#[derive(Debug, Message)]
+pub struct PlayerLeft {
+	pub client_id: u32,
+}
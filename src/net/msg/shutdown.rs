@@ -0,0 +1,13 @@
+use naia_bevy_shared::Message;
+
+/// Sent to a client just before the server drops it for good, or goes away entirely
+#[derive(Message)]
+pub struct Shutdown {
+	pub reason: String,
+}
+
+impl Shutdown {
+	pub fn new(reason: &str) -> Self {
+		Shutdown{ reason: reason.to_string() }
+	}
+}
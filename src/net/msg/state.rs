@@ -0,0 +1,31 @@
+use bevy::math::Vec2;
+use naia_bevy_shared::{Message, Tick};
+use crate::net::quant::QuantPos;
+
+/// Authoritative simulation results for the receiving client's own player
+#[derive(Debug, Message)]
+pub struct PlayerState {
+	pub tick: Tick,
+	pub pos: QuantPos,
+	pub vel_x: f32,
+	pub vel_y: f32,
+}
+
+impl PlayerState {
+	pub fn new(tick: Tick, pos: Vec2, vel: Vec2) -> Self {
+		Self {
+			tick,
+			pos: QuantPos::new(pos),
+			vel_x: vel.x,
+			vel_y: vel.y,
+		}
+	}
+
+	pub fn pos(&self) -> Vec2 {
+		self.pos.get()
+	}
+
+	pub fn vel(&self) -> Vec2 {
+		Vec2::new(self.vel_x, self.vel_y)
+	}
+}
@@ -15,6 +15,11 @@ pub struct TickState {
 	pub ticks_pending: usize,
 }
 
+/// Signed number of ticks from `a` to `b`, accounting for wrap around
+pub fn tick_diff(a: Tick, b: Tick) -> i16 {
+	b.wrapping_sub(a) as i16
+}
+
 pub fn sys_run_tick_schedules(world: &mut World) {
 	world.run_schedule(TickSchedule::Network);
 	world.run_schedule(TickSchedule::PreTicks);
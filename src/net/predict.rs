@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+use naia_bevy_client::events::MessageEvents;
+use naia_bevy_shared::{ReceiveEvents, sequence_greater_than, Tick};
+use std::collections::VecDeque;
+use crate::{
+	collide::{Collidable, PosedShapes},
+	movement::{Position, Velocity},
+	player::{Intent, Player, PlayerId},
+	sim::{self, move_player, push_apart, Separation, Static, Statics, PLAYER_SPEED},
+	tick_schedule::{TickConfig, TickSchedule},
+};
+use super::{
+	config::StateChannel,
+	msg,
+	peer::TickState,
+};
+
+// ~2s @ 60 ticks/s; anything older than this can't be reconciled anyway
+const HISTORY_LEN: usize = 128;
+
+// client and server run the same code, so anything beyond rounding is a misprediction
+const TOLERANCE: f32 = 0.01;
+
+/// Predicts the local player ahead of the server, re-simulating from the latest
+/// authoritative state whenever our prediction turns out to be wrong.
+pub struct PredictionPlugin;
+
+impl Plugin for PredictionPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(Prediction::default())
+			.add_systems(TickSchedule::Network, sys_event_state.in_set(ReceiveEvents))
+			.add_systems(TickSchedule::PreTicks, sys_reconcile)
+			.add_systems(TickSchedule::Tick, sys_record.after(sim::sys_move_player));
+	}
+}
+
+/// Marks the entity whose movement is predicted from local input
+#[derive(Component, Default)]
+pub struct Predicted;
+
+#[derive(Clone, Copy, Debug)]
+struct Snapshot {
+	tick: Tick,
+	intent: Intent,
+	pos: Vec2,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Authority {
+	tick: Tick,
+	pos: Vec2,
+	vel: Vec2,
+}
+
+#[derive(Default, Resource)]
+pub struct Prediction {
+	history: VecDeque<Snapshot>,
+	authority: Option<Authority>,
+	pub corrections: u32,
+	pub last_error: f32,
+}
+
+impl Prediction {
+	fn record(&mut self, snapshot: Snapshot) {
+		if self.history.len() >= HISTORY_LEN {
+			self.history.pop_front();
+		}
+		self.history.push_back(snapshot);
+	}
+
+	fn forget_through(&mut self, tick: Tick) {
+		while let Some(s) = self.history.front() {
+			if sequence_greater_than(s.tick, tick) {
+				break;
+			}
+			self.history.pop_front();
+		}
+	}
+}
+
+fn sys_event_state(
+	mut prediction: ResMut<Prediction>,
+	mut event_sets: EventReader<MessageEvents>,
+) {
+	for events in event_sets.read() {
+		for state in events.read::<StateChannel, msg::PlayerState>() {
+			if let Some(auth) = &prediction.authority {
+				if !sequence_greater_than(state.tick, auth.tick) {
+					continue;
+				}
+			}
+
+			prediction.authority = Some(Authority {
+				tick: state.tick,
+				pos: state.pos(),
+				vel: state.vel(),
+			});
+		}
+	}
+}
+
+fn sys_record(
+	state: Res<TickState>,
+	mut prediction: ResMut<Prediction>,
+	q_player: Query<(&Intent, &Position), With<Predicted>>,
+) {
+	let (intent, pos) = unwrap!(q_player.get_single().ok(), { return; });
+
+	prediction.record(Snapshot {
+		tick: state.cur_tick,
+		intent: *intent,
+		pos: pos.p,
+	});
+}
+
+#[allow(clippy::type_complexity)]
+fn sys_reconcile(
+	statics: Res<Statics>,
+	tick: Res<TickConfig>,
+	separation: Res<Separation>,
+	mut prediction: ResMut<Prediction>,
+	mut q_player: Query<(Entity, &PlayerId, &Collidable, &mut Position, &mut Velocity), (With<Player>, With<Predicted>)>,
+	q_others: Query<(Entity, &PlayerId, &Collidable, &Position), (With<Player>, Without<Predicted>)>,
+	q_statics: Query<(Entity, &Collidable, &Position), (With<Static>, Without<Player>)>,
+) {
+	let auth = unwrap!(prediction.authority.take(), { return; });
+	let (ent, id, col, mut pos, mut vel) = unwrap!(q_player.get_single_mut().ok(), { return; });
+
+	let oldest = unwrap!(prediction.history.front().map(|s| s.tick), {
+		// nothing predicted yet, so the server is all we have
+		pos.p = auth.pos;
+		vel.v = auth.vel;
+		return;
+	});
+
+	if sequence_greater_than(oldest, auth.tick) {
+		// too old to tell what we predicted back then
+		return;
+	}
+
+	let predicted = prediction.history.iter()
+		.find(|s| s.tick == auth.tick)
+		.map(|s| s.pos);
+	prediction.forget_through(auth.tick);
+
+	let error = predicted.map_or(f32::MAX, |p| p.distance(auth.pos));
+	if error <= TOLERANCE {
+		return;
+	}
+
+	prediction.corrections += 1;
+	prediction.last_error = error;
+
+	// rewind to the server's result, then replay everything we've predicted since,
+	// against everyone else as they are now; the best guess we've got
+
+	// in the same order the server takes them, so the same pushes add up the same
+	let mut others: Vec<_> = q_others.iter()
+		.map(|(ent, id, col, pos)| (*id, ent, col.clone(), pos.clone()))
+		.collect();
+	others.sort_by_key(|(id, ..)| *id);
+	let players = PosedShapes::new(others.iter()
+		.map(|(_, ent, col, pos)| (*ent, col.shape.clone(), pos.clone())));
+	let others: Vec<_> = others.into_iter()
+		.map(|(id, _, col, pos)| (id, col, pos))
+		.collect();
+
+	let step_secs = tick.step.as_secs_f32();
+	pos.p = auth.pos;
+	vel.v = auth.vel;
+	for snapshot in prediction.history.iter_mut() {
+		vel.v = PLAYER_SPEED * snapshot.intent.dir;
+		move_player(&q_statics, &statics, &players.excluding(ent), col, &mut pos, &vel, step_secs);
+		if separation.0 > 0.0 {
+			let push = push_apart(*id, col, &pos, &others);
+			pos.p += separation.0 * push;
+		}
+		snapshot.pos = pos.p;
+	}
+}
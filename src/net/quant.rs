@@ -0,0 +1,173 @@
+use bevy::math::Vec2;
+use naia_bevy_shared::{Serde, SignedInteger, UnsignedInteger};
+use crate::sim::MAP_EXTENT;
+
+// Compact, lossy encodings for what we send most often. Each decodes to the
+// same value on every machine, so whoever sends one should simulate with the
+// decoded value too, lest it drift from what everyone else sees. Changing any
+// of them changes the wire format, so bump `WIRE_REVISION` in config.rs too.
+
+// steps either side of zero for each axis of movement; 3 bits plus a sign
+const DIR_STEPS: f32 = 7.0;
+
+// steps either side of zero for each coordinate; 17 bits plus a sign
+const POS_STEPS: f32 = 131_071.0;
+
+// steps per turn; 16 bits
+const ANGLE_STEPS: f32 = 65_536.0;
+
+/// A movement direction, accurate to within 0.1 per axis. Decodes to at most
+/// unit length.
+#[derive(Clone, Copy, Debug, PartialEq, Serde)]
+pub struct QuantDir {
+	x: SignedInteger<3>,
+	y: SignedInteger<3>,
+}
+
+impl QuantDir {
+	pub fn new(dir: Vec2) -> Self {
+		let quantize = |v: f32| (v.clamp(-1.0, 1.0) * DIR_STEPS).round() as i8;
+		Self {
+			x: SignedInteger::new(quantize(dir.x)),
+			y: SignedInteger::new(quantize(dir.y)),
+		}
+	}
+
+	pub fn get(&self) -> Vec2 {
+		let dir = Vec2::new(self.x.get() as f32, self.y.get() as f32) / DIR_STEPS;
+		dir.clamp_length_max(1.0)
+	}
+}
+
+/// An angle in turns, accurate to within 1/131072 of a turn. Decodes to [0, 1).
+#[derive(Clone, Copy, Debug, PartialEq, Serde)]
+pub struct QuantAngle {
+	steps: UnsignedInteger<16>,
+}
+
+impl QuantAngle {
+	pub fn new(turns: f32) -> Self {
+		let steps = (turns.rem_euclid(1.0) * ANGLE_STEPS).round() as u32 % ANGLE_STEPS as u32;
+		Self { steps: UnsignedInteger::new(steps) }
+	}
+
+	pub fn get(&self) -> f32 {
+		self.steps.get() as f32 / ANGLE_STEPS
+	}
+}
+
+/// A position on the map in fixed point, accurate to within 0.01 units on
+/// either axis. Anything outside `MAP_EXTENT` is pulled back to its edge.
+#[derive(Clone, Copy, Debug, PartialEq, Serde)]
+pub struct QuantPos {
+	x: SignedInteger<17>,
+	y: SignedInteger<17>,
+}
+
+impl QuantPos {
+	pub fn new(pos: Vec2) -> Self {
+		let quantize = |v: f32, extent: f32| {
+			(v.clamp(-extent, extent) / extent * POS_STEPS).round() as i32
+		};
+		Self {
+			x: SignedInteger::new(quantize(pos.x, MAP_EXTENT.x)),
+			y: SignedInteger::new(quantize(pos.y, MAP_EXTENT.y)),
+		}
+	}
+
+	pub fn get(&self) -> Vec2 {
+		Vec2::new(self.x.get() as f32, self.y.get() as f32) / POS_STEPS * MAP_EXTENT
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use naia_bevy_shared::{BitReader, BitWriter};
+	use std::f32::consts::TAU;
+	use super::*;
+
+	// what reaches the other side, not just what the constructor made of it
+	fn round_trip<T: Serde>(value: &T) -> T {
+		let mut writer = BitWriter::new();
+		value.ser(&mut writer);
+		let bytes = writer.to_bytes();
+		T::de(&mut BitReader::new(&bytes)).expect("deserializes")
+	}
+
+	// shortest way round between two angles in turns
+	fn turns_apart(a: f32, b: f32) -> f32 {
+		let d = (a - b).rem_euclid(1.0);
+		d.min(1.0 - d)
+	}
+
+	#[test]
+	fn dir_within_bounds() {
+		for i in 0..360 {
+			let angle = i as f32 / 360.0 * TAU;
+			for len in [0.0, 0.25, 0.5, 0.75, 1.0] {
+				let dir = len * Vec2::from_angle(angle);
+				let got = round_trip(&QuantDir::new(dir)).get();
+				assert!((got.x - dir.x).abs() <= 0.1, "{:?} -> {:?}", dir, got);
+				assert!((got.y - dir.y).abs() <= 0.1, "{:?} -> {:?}", dir, got);
+				assert!(got.length() <= 1.0 + f32::EPSILON, "{:?} -> {:?}", dir, got);
+			}
+		}
+	}
+
+	#[test]
+	fn dir_clamps_overlong() {
+		let got = round_trip(&QuantDir::new(Vec2::new(5.0, -5.0))).get();
+		assert!(got.length() <= 1.0 + f32::EPSILON);
+		assert!(got.x > 0.0 && got.y < 0.0);
+		assert_eq!(QuantDir::new(Vec2::ZERO).get(), Vec2::ZERO);
+	}
+
+	#[test]
+	fn angle_within_bounds() {
+		for i in 0..=10_000 {
+			let turns = i as f32 / 10_000.0;
+			let got = round_trip(&QuantAngle::new(turns)).get();
+			assert!((0.0..1.0).contains(&got), "{} -> {}", turns, got);
+			assert!(turns_apart(got, turns) <= 1.0 / 131_072.0 + f32::EPSILON, "{} -> {}", turns, got);
+		}
+	}
+
+	#[test]
+	fn angle_wraps_around() {
+		assert_eq!(QuantAngle::new(0.0).get(), 0.0);
+		assert_eq!(QuantAngle::new(1.0).get(), 0.0);
+		assert_eq!(QuantAngle::new(-1.0).get(), 0.0);
+		assert_eq!(QuantAngle::new(1.0 - 1e-7).get(), 0.0);
+		assert_eq!(QuantAngle::new(1.25), QuantAngle::new(0.25));
+		assert_eq!(QuantAngle::new(-0.25), QuantAngle::new(0.75));
+	}
+
+	#[test]
+	fn pos_within_bounds() {
+		let n = 1000;
+		for i in -n..=n {
+			let f = i as f32 / n as f32;
+			let pos = Vec2::new(f * MAP_EXTENT.x, -f * MAP_EXTENT.y);
+			let got = round_trip(&QuantPos::new(pos)).get();
+			assert!((got.x - pos.x).abs() <= 0.01, "{:?} -> {:?}", pos, got);
+			assert!((got.y - pos.y).abs() <= 0.01, "{:?} -> {:?}", pos, got);
+		}
+	}
+
+	#[test]
+	fn pos_at_extent() {
+		for corner in [MAP_EXTENT, -MAP_EXTENT, Vec2::new(MAP_EXTENT.x, -MAP_EXTENT.y)] {
+			let got = round_trip(&QuantPos::new(corner)).get();
+			assert!(got.distance(corner) <= 0.01, "{:?} -> {:?}", corner, got);
+		}
+	}
+
+	#[test]
+	fn pos_clamps_past_extent() {
+		for past in [MAP_EXTENT + 0.5, -MAP_EXTENT - 0.5, MAP_EXTENT * 10.0, Vec2::splat(f32::MAX)] {
+			let got = round_trip(&QuantPos::new(past)).get();
+			let edge = past.clamp(-MAP_EXTENT, MAP_EXTENT);
+			assert!(got.distance(edge) <= 0.01, "{:?} -> {:?}", past, got);
+		}
+	}
+}
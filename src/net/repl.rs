@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use naia_bevy_shared::{Property, Replicate};
+use crate::{
+	movement::{Facing, Position, Velocity},
+	player::Team,
+};
+use super::quant::{QuantAngle, QuantPos};
+
+// Replicated mirrors of the simulation components. The server copies its
+// results into these, and clients copy them back out onto their proxies.
+
+#[derive(Component, Replicate)]
+pub struct NetPlayer {
+	pub client_id: Property<u32>,
+	pub team: Property<u8>,
+}
+
+impl NetPlayer {
+	pub fn new(client_id: u32, team: Team) -> Self {
+		Self::new_complete(client_id, team.index())
+	}
+
+	pub fn team(&self) -> Team {
+		Team::from_index(*self.team)
+	}
+}
+
+#[derive(Component, Replicate)]
+pub struct NetShot {
+	pub team: Property<u8>,
+	pub bounces: Property<u8>,
+}
+
+impl NetShot {
+	pub fn new(team: Team, bounces: u8) -> Self {
+		Self::new_complete(team.index(), bounces)
+	}
+
+	pub fn team(&self) -> Team {
+		Team::from_index(*self.team)
+	}
+}
+
+#[derive(Component, Replicate)]
+pub struct NetPosition {
+	pub pos: Property<QuantPos>,
+}
+
+impl NetPosition {
+	pub fn to_vec2(&self) -> Vec2 {
+		self.pos.get()
+	}
+
+	pub fn set(&mut self, p: Vec2) {
+		// only touch what changed, to avoid sending redundant updates
+		let pos = QuantPos::new(p);
+		if *self.pos != pos {
+			*self.pos = pos;
+		}
+	}
+}
+
+impl From<&Position> for NetPosition {
+	fn from(pos: &Position) -> Self {
+		Self::new_complete(QuantPos::new(pos.p))
+	}
+}
+
+#[derive(Component, Replicate)]
+pub struct NetVelocity {
+	pub x: Property<f32>,
+	pub y: Property<f32>,
+}
+
+impl NetVelocity {
+	pub fn to_vec2(&self) -> Vec2 {
+		Vec2::new(*self.x, *self.y)
+	}
+
+	pub fn set(&mut self, v: Vec2) {
+		if *self.x != v.x {
+			*self.x = v.x;
+		}
+		if *self.y != v.y {
+			*self.y = v.y;
+		}
+	}
+}
+
+impl From<&Velocity> for NetVelocity {
+	fn from(vel: &Velocity) -> Self {
+		Self::new_complete(vel.v.x, vel.v.y)
+	}
+}
+
+#[derive(Component, Replicate)]
+pub struct NetFacing {
+	pub angle: Property<QuantAngle>,
+}
+
+impl NetFacing {
+	pub fn turns(&self) -> f32 {
+		self.angle.get()
+	}
+
+	pub fn set(&mut self, turns: f32) {
+		let angle = QuantAngle::new(turns);
+		if *self.angle != angle {
+			*self.angle = angle;
+		}
+	}
+}
+
+impl From<&Facing> for NetFacing {
+	fn from(facing: &Facing) -> Self {
+		Self::new_complete(QuantAngle::new(facing.turns))
+	}
+}
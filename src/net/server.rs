@@ -1,9 +1,10 @@
 use bevy::{
-	app::{RunMode, ScheduleRunnerPlugin},
+	app::AppExit,
 	prelude::*
 };
 use naia_bevy_server::{
-	events::{AuthEvents, ConnectEvent, DisconnectEvent, ErrorEvent, TickEvent},
+	CommandsExt,
+	events::{AuthEvents, ConnectEvent, DisconnectEvent, ErrorEvent, MessageEvents, TickEvent},
 	Plugin as NaiaServerPlugin,
 	RoomKey,
 	Server,
@@ -14,51 +15,141 @@ use naia_bevy_server::{
 use naia_bevy_shared::ReceiveEvents;
 use std::{
 	collections::HashMap,
+	net::SocketAddr,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, Ordering},
+	},
 	thread,
-	time::Instant
+	time::{Duration, Instant},
+};
+use crate::{
+	args::app_version,
+	movement::{Facing, Position, Velocity},
+	net::config::CmdStreamChannel,
+	player::{Intent, Player, PlayerId, Team},
+	sim::{player_bundle, Shot, systems_tick},
+	tick_schedule::{single_thread_schedule, TickConfig, TickSchedule},
 };
-use crate::net::config::CmdStreamChannel;
 
 use super::{
-	config::{self, InputSrcChannel, TICK_INTERVAL},
+	config::{
+		CONNECTION_TIMEOUT,
+		InputReplChannel,
+		InputSrcChannel,
+		Link,
+		protocol_version,
+		StateChannel,
+		TICK_INTERVAL,
+	},
+	admin::{AdminPlugin, Bans, KICK_GRACE},
+	auth::{Identity, ServerAuth},
+	chat::ChatServerPlugin,
+	discovery::DiscoveryServerPlugin,
+	input_buffer::{InputBuffer, InputStats, Received},
+	lag_comp::LagCompPlugin,
 	msg,
 	peer::*,
+	repl::{NetFacing, NetPlayer, NetPosition, NetShot, NetVelocity},
+	session::Sessions,
+	stats::{BANDWIDTH_WINDOW, sys_log_server_stats},
 };
 
+// long enough to notice the drop, back off and reconnect a few times
+const SESSION_GRACE: Duration = Duration::from_secs(30);
+
+// long enough for the shutdown notice to reach everyone, give or take a resend
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// Runs the authoritative game. Needs no window, audio or assets, so it can run
+/// headless under `MinimalPlugins`, or on its own thread next to a client.
 pub struct NetServerPlugin;
 
 impl Plugin for NetServerPlugin {
 	fn build(&self, app: &mut App) {
+		// insert a `Link` ahead of the plugin to simulate network conditions
+		let link = app.world.get_resource::<Link>().cloned().unwrap_or_default();
+
 		app
 			.add_plugins((
-				ScheduleRunnerPlugin{ run_mode: RunMode::Loop { wait: None} },
 				NaiaServerPlugin::new(
-					ServerConfig::default(),
-					config::global_avg(),
+					server_config(),
+					link.protocol(),
 				),
+				AdminPlugin,
+				ChatServerPlugin,
+				DiscoveryServerPlugin,
+				LagCompPlugin,
 			))
+			// assume clients interpolate as we would, to estimate what they saw
+			.insert_resource(link.interp)
+			.init_resource::<InputStats>()
+			.init_resource::<ServerAuth>()
+			.init_resource::<Sessions>()
+			.init_resource::<ShutdownSignal>()
 			.insert_resource(SleepContext{ frame_start: Instant::now() })
+			.insert_resource(TickConfig {
+				budget: TICK_INTERVAL,
+				interval: TICK_INTERVAL,
+				step: TICK_INTERVAL,
+			})
+			.insert_resource(TickState::default())
+			.add_schedule(single_thread_schedule(TickSchedule::Tick))
+			.add_systems(TickSchedule::Tick, (
+				sys_apply_input,
+				systems_tick(),
+				sys_repl_shots,
+				sys_repl_sync,
+				sys_send_state,
+			).chain())
 			.add_systems(Update, (
 				sys_event_auth,
 				sys_event_connect,
 				sys_event_disconnect,
+				sys_expire_sessions,
 				sys_event_error,
-				sys_event_msg,
+				sys_event_input,
+				sys_run_ticks,
+				sys_log_server_stats,
+				sys_update_scopes,
+				sys_shutdown,
 				sys_sleep,
 			).chain().in_set(ReceiveEvents))
 			.add_systems(Startup, sys_start);
 	}
 }
 
+fn server_config() -> ServerConfig {
+	let mut config = ServerConfig::default();
+	config.connection.disconnection_timeout_duration = CONNECTION_TIMEOUT;
+	config.connection.bandwidth_measure_duration = Some(BANDWIDTH_WINDOW);
+	config
+}
+
+/// Address for the server to bind to
+#[derive(Clone, Copy, Debug, Resource)]
+pub struct ListenAddr(pub SocketAddr);
+
+/// Set from anywhere (e.g. a signal handler) to have the server shut down
+#[derive(Clone, Default, Resource)]
+pub struct ShutdownSignal(pub Arc<AtomicBool>);
+
 #[derive(Resource)]
 pub struct ServerContext {
 	pub room: RoomKey,
-    pub client_ids: HashMap<UserKey, u32>,
-	pub next_client_id: u32,
+	/// Authenticated, but not yet connected
+	pub identities: HashMap<UserKey, Identity>,
+	/// Connections we only accepted to tell them why they're rejected; anything
+	/// they send is ignored until they're disconnected
+	pub rejections: HashMap<UserKey, String>,
+	/// Authenticated, and asking to pick up where they left off
+	pub resuming: HashMap<UserKey, u64>,
+	/// Kicked, and due to be disconnected once they've heard why
+	pub kicking: HashMap<UserKey, Instant>,
 }
 
-pub fn sys_start(mut commands: Commands, mut server: Server) {
-	let addr = udp_sock_addr((127, 0, 0, 1), 5323);
+pub fn sys_start(mut commands: Commands, mut server: Server, addr: Res<ListenAddr>) {
+	let addr = addr.0;
 	let sock = udp::Socket::new(&addr, None);
 
 	println!("Starting server on {}...", addr);
@@ -67,8 +158,10 @@ pub fn sys_start(mut commands: Commands, mut server: Server) {
 	// Resources
 	commands.insert_resource(ServerContext {
 		room: server.make_room().key(),
-		client_ids: HashMap::new(),
-		next_client_id: 1,
+		identities: HashMap::new(),
+		rejections: HashMap::new(),
+		resuming: HashMap::new(),
+		kicking: HashMap::new(),
 	});
 }
 
@@ -87,49 +180,204 @@ fn sys_sleep(mut ctx: ResMut<SleepContext>) {
 	ctx.frame_start += TICK_INTERVAL;
 }
 
+/// Warns everyone once shutdown is requested, then exits after a short grace period
+fn sys_shutdown(
+	mut server: Server,
+	signal: Res<ShutdownSignal>,
+	mut deadline: Local<Option<Instant>>,
+	mut exit: EventWriter<AppExit>,
+) {
+	if !signal.0.load(Ordering::Relaxed) {
+		return;
+	}
+
+	match *deadline {
+		None => {
+			println!("Shutting down...");
+			let msg = msg::Shutdown::new("server shutting down");
+			server.broadcast_message::<CmdStreamChannel, msg::Shutdown>(&msg);
+			*deadline = Some(Instant::now() + SHUTDOWN_GRACE);
+		},
+		Some(t) if Instant::now() >= t => {
+			println!("Shutdown complete");
+			exit.send(AppExit);
+		},
+		_ => {},
+	}
+}
+
 pub fn sys_event_auth(
 	mut events: EventReader<AuthEvents>,
+	mut ctx: ResMut<ServerContext>,
 	mut server: Server,
+	auth: Res<ServerAuth>,
+	bans: Res<Bans>,
+	sessions: Res<Sessions>,
 ) {
 	for events in events.read() {
-		for (uid, auth) in events.read::<msg::Auth>() {
-			let user = server.user(&uid);
+		for (uid, msg) in events.read::<msg::Auth>() {
+			let addr = server.user(&uid).address();
 
-			println!("Received auth request from {} with token '{}'", user.address(), auth.token);
-			//server.reject_connection(uid);
+			let joining = ctx.identities.len() - ctx.resuming.len();
+			let result = match auth.0.authenticate(&msg) {
+				_ if msg.protocol != protocol_version() => Err(format!(
+					"incompatible version; you run {} (protocol {:016x}), the server runs {} (protocol {:016x})",
+					msg.version,
+					msg.protocol,
+					app_version(),
+					protocol_version(),
+				)),
+				_ if bans.contains(&addr.ip()) => Err("banned".to_string()),
+				result => result,
+			};
+
+			// a session id alone isn't enough to take over someone else's player
+			let session = result.as_ref().ok().and_then(|identity| {
+				let id = msg.session?;
+				let s = sessions.get(id)?;
+				(player_name(Some(identity), s.client_id) == s.name).then_some(id)
+			});
+
+			let result = match result {
+				// resumed sessions already hold a slot
+				Ok(_) if session.is_none() && sessions.is_full(joining) =>
+					Err("server is full".to_string()),
+				result => result,
+			};
+
+			match result {
+				Ok(identity) => {
+					println!("Accepted {} as '{}'", addr, identity.name);
+					ctx.identities.insert(uid, identity);
+					if let Some(session) = session {
+						ctx.resuming.insert(uid, session);
+					}
+				},
+				Err(reason) => {
+					// naia's rejections can't carry a reason, so let them in just
+					// long enough to hear it
+					println!("Rejected {}: {}", addr, reason);
+					ctx.rejections.insert(uid, reason);
+				},
+			}
 			server.accept_connection(&uid);
 		}
 	}
 }
 
-pub fn sys_event_connect<'world, 'state>(
+/// What whoever authenticated as `identity` plays as
+fn player_name(identity: Option<&Identity>, client_id: u32) -> String {
+	identity
+		.map(|i| i.name.clone())
+		.filter(|n| !n.is_empty())
+		.unwrap_or_else(|| format!("Player {}", client_id))
+}
+
+pub fn sys_event_connect(
+	mut cmds: Commands,
 	mut events: EventReader<ConnectEvent>,
 	mut ctx: ResMut<ServerContext>,
+	mut sessions: ResMut<Sessions>,
 	mut server: Server,
 ) {
 	for ConnectEvent(uid) in events.read() {
-		// assign client Id
-		let client_id = ctx.next_client_id;
-		ctx.next_client_id = client_id.wrapping_add(1);
-		ctx.client_ids.insert(*uid, client_id);
-
-		// send assignment
-		let msg = msg::Assign { client_id };
-		server.send_message::<CmdStreamChannel, msg::Assign>(uid, &msg);
+		if let Some(reason) = ctx.rejections.get(uid) {
+			let msg = msg::Reject::new(reason);
+			server.send_message::<CmdStreamChannel, msg::Reject>(uid, &msg);
+			// don't count on them hanging up by themselves
+			ctx.kicking.insert(*uid, Instant::now() + KICK_GRACE);
+			continue;
+		}
+		let identity = ctx.identities.remove(uid);
 
 		let mut user = server.user_mut(uid);
 		println!("Client connected from {}", user.address());
 		user.enter_room(&ctx.room);
 
+		if let Some(session_id) = ctx.resuming.remove(uid) {
+			// whatever connection held it before is dead to us now
+			if let Some(session) = sessions.resume(session_id, *uid) {
+				println!("Resuming session of client {}", session.client_id);
+				let msg = msg::Assign {
+					client_id: session.client_id,
+					team: session.team.index(),
+					session: session_id,
+				};
+				server.send_message::<CmdStreamChannel, msg::Assign>(uid, &msg);
+				continue;
+			}
+		}
+
+		let client_id = sessions.alloc_client_id();
+
+		// spawn the authoritative player
+		let name = player_name(identity.as_ref(), client_id);
+		let team = Team::from_index(client_id as u8);
+		let pos = Position::ZERO;
+		let ent = cmds
+			.spawn((
+				NetPlayer::new(client_id, team),
+				NetPosition::from(&pos),
+				NetVelocity::from(&Velocity::ZERO),
+				NetFacing::from(&Facing::default()),
+				player_bundle(PlayerId(client_id), &name, team, pos),
+				InputBuffer::default(),
+			))
+			.enable_replication(&mut server)
+			.id();
+		server.room_mut(&ctx.room).add_entity(&ent);
+		let session = sessions.open(*uid, client_id, name, team, ent);
+
+		// send assignment
+		let msg = msg::Assign { client_id, team: team.index(), session };
+		server.send_message::<CmdStreamChannel, msg::Assign>(uid, &msg);
+
 		// TODO -- send world state here
 	}
 }
 
+/// Parks the player of whoever left, in case they come back
 pub fn sys_event_disconnect(
 	mut events: EventReader<DisconnectEvent>,
+	mut ctx: ResMut<ServerContext>,
+	mut sessions: ResMut<Sessions>,
+	mut q_intent: Query<(&mut Intent, &mut InputBuffer)>,
 ) {
-	for DisconnectEvent(_uid, user) in events.read() {
+	for DisconnectEvent(uid, user) in events.read() {
 		println!("Client disconnected from {}", user.address);
+		ctx.identities.remove(uid);
+		ctx.rejections.remove(uid);
+		ctx.resuming.remove(uid);
+		ctx.kicking.remove(uid);
+
+		let session = unwrap!(sessions.suspend(uid, Instant::now() + SESSION_GRACE), { continue; });
+		if let Ok((mut intent, mut inputs)) = q_intent.get_mut(session.ent) {
+			*intent = Intent::default();
+			*inputs = InputBuffer::default();
+		}
+	}
+}
+
+/// Removes the players of anyone who didn't come back in time
+pub fn sys_expire_sessions(
+	mut cmds: Commands,
+	mut server: Server,
+	mut sessions: ResMut<Sessions>,
+	q_shots: Query<(Entity, &Shot)>,
+) {
+	for session in sessions.expire(Instant::now()) {
+		println!("{} (client {}) left", session.name, session.client_id);
+		cmds.entity(session.ent).despawn_recursive();
+
+		// take their shots with them, since nobody is left to own them
+		for (shot_ent, shot) in &q_shots {
+			if shot.shooter == Some(session.ent) {
+				cmds.entity(shot_ent).despawn_recursive();
+			}
+		}
+
+		let msg = msg::PlayerLeft { client_id: session.client_id };
+		server.broadcast_message::<CmdStreamChannel, msg::PlayerLeft>(&msg);
 	}
 }
 
@@ -141,17 +389,152 @@ pub fn sys_event_error(
 	}
 }
 
-pub fn sys_event_msg(
-	mut ticks: EventReader<TickEvent>,
+/// Runs the simulation once for every tick naia has elapsed since last frame
+pub fn sys_run_ticks(world: &mut World) {
+	let ticks: Vec<_> = world.resource_mut::<Events<TickEvent>>()
+		.drain()
+		.map(|t| t.0)
+		.collect();
+
+	for tick in ticks {
+		world.resource_mut::<TickState>().cur_tick = tick;
+		world.run_schedule(TickSchedule::Tick);
+	}
+}
+
+/// Files away every input frame that hasn't been seen yet, until its tick comes up
+pub fn sys_event_input(
+	mut event_sets: EventReader<MessageEvents>,
+	sessions: Res<Sessions>,
+	mut stats: ResMut<InputStats>,
+	mut q_inputs: Query<&mut InputBuffer>,
+) {
+	for events in event_sets.read() {
+		for (uid, msg) in events.read::<InputSrcChannel, msg::Input>() {
+			let ent = unwrap!(sessions.entity_of(&uid), { continue; });
+			let mut inputs = unwrap!(q_inputs.get_mut(ent).ok(), { continue; });
+
+			// only what falls in a gap after the input we'd already heard of was
+			// lost; the history in a first message, or behind it, wasn't
+			let newest = inputs.newest();
+			for (i, (tick, frame)) in msg.frames().enumerate() {
+				let missed = newest.is_some_and(|n| tick_diff(n, tick) > 0);
+				match (i, inputs.insert(tick, frame)) {
+					(0, Received::Late) => stats.late += 1,
+					(1.., Received::Fresh) if missed => stats.recovered += 1,
+					_ => {},
+				}
+			}
+		}
+	}
+}
+
+/// Applies everyone's input for this tick, falling back on their last input
+/// when it never arrived
+pub fn sys_apply_input(
+	mut server: Server,
+	state: Res<TickState>,
+	sessions: Res<Sessions>,
+	mut stats: ResMut<InputStats>,
+	mut q_inputs: Query<(&mut Intent, &mut InputBuffer)>,
+) {
+	let mut applied = Vec::new();
+	for (_, session) in sessions.connected() {
+		let (mut intent, mut inputs) = unwrap!(q_inputs.get_mut(session.ent).ok(), { continue; });
+		let frame = unwrap!(inputs.take(state.cur_tick), {
+			if inputs.has_input() {
+				stats.missing += 1;
+			}
+			continue;
+		});
+		*intent = frame.to_intent();
+		applied.push((session.client_id, frame));
+	}
+
+	// everyone needs everyone else's to drive their proxies of them; one message
+	// each, which they pick their own out of
+	if applied.is_empty() {
+		return;
+	}
+	let msg = msg::InputRepl { frames: applied };
+	//info!("sys_apply_input {:?}: {:?}", state.cur_tick, msg);
+	for (uid, _) in sessions.connected() {
+		server.send_message::<InputReplChannel, msg::InputRepl>(&uid, &msg);
+	}
+}
+
+/// Starts replicating shots spawned by the simulation
+pub fn sys_repl_shots(
+	mut cmds: Commands,
 	mut server: Server,
 	ctx: Res<ServerContext>,
+	q_shots: Query<(Entity, &Shot, &Team, &Position, &Velocity), Without<NetShot>>,
+) {
+	for (ent, shot, team, pos, vel) in &q_shots {
+		cmds.entity(ent)
+			.insert((
+				NetShot::new(*team, shot.bounces),
+				NetPosition::from(pos),
+				NetVelocity::from(vel),
+			))
+			.enable_replication(&mut server);
+		server.room_mut(&ctx.room).add_entity(&ent);
+	}
+}
+
+/// Copies simulation results into their replicated counterparts
+pub fn sys_repl_sync(
+	mut q_pos: Query<(&Position, &mut NetPosition), Changed<Position>>,
+	mut q_vel: Query<(&Velocity, &mut NetVelocity), Changed<Velocity>>,
+	mut q_facing: Query<(&Facing, &mut NetFacing), Changed<Facing>>,
+	mut q_shot: Query<(&Shot, &mut NetShot), Changed<Shot>>,
+) {
+	for (pos, mut net) in &mut q_pos {
+		net.set(pos.p);
+	}
+	for (vel, mut net) in &mut q_vel {
+		net.set(vel.v);
+	}
+	for (facing, mut net) in &mut q_facing {
+		net.set(facing.turns);
+	}
+	for (shot, mut net) in &mut q_shot {
+		if *net.bounces != shot.bounces {
+			*net.bounces = shot.bounces;
+		}
+	}
+}
+
+/// Everyone sees everything, except their own player and shots, which they
+/// predict locally instead
+pub fn sys_update_scopes(
+	mut server: Server,
+	sessions: Res<Sessions>,
+	q_shots: Query<&Shot>,
+) {
+	for (_, uid, ent) in server.scope_checks() {
+		let own = sessions.entity_of(&uid);
+		let shooter = q_shots.get(ent).ok().and_then(|s| s.shooter);
+
+		let mut scope = server.user_scope(&uid);
+		if Some(ent) == own || (shooter.is_some() && shooter == own) {
+			scope.exclude(&ent);
+		} else {
+			scope.include(&ent);
+		}
+	}
+}
+
+pub fn sys_send_state(
+	mut server: Server,
+	state: Res<TickState>,
+	sessions: Res<Sessions>,
+	q_player: Query<(&Position, &Velocity), With<Player>>,
 ) {
-	for t in ticks.read() {
-		let mut messages = server.receive_tick_buffer_messages(&t.0);
-		for (uid, msg) in messages.read::<InputSrcChannel, msg::Input>() {
-			let msg = msg::InputRepl::new(ctx.client_ids[&uid], &msg);
-			//info!("sys_event_msg {:?}: {:?}", state.cur_tick, msg);
-			server.broadcast_message::<CmdStreamChannel, msg::InputRepl>(&msg);
+	for (uid, session) in sessions.connected() {
+		if let Ok((pos, vel)) = q_player.get(session.ent) {
+			let msg = msg::PlayerState::new(state.cur_tick, pos.p, vel.v);
+			server.send_message::<StateChannel, msg::PlayerState>(&uid, &msg);
 		}
 	}
 }
@@ -0,0 +1,182 @@
+use bevy::{
+	ecs::system::SystemParam,
+	prelude::*,
+};
+use naia_bevy_server::UserKey;
+use std::{
+	collections::{BTreeSet, HashMap},
+	time::Instant,
+};
+use crate::player::Team;
+
+const DEFAULT_MAX_PLAYERS: usize = 16;
+
+/// A player's claim to their client id and entity, which outlives any one
+/// connection for a little while, so dropped players can resume in place
+pub struct Session {
+	pub client_id: u32,
+	pub ent: Entity,
+	pub name: String,
+	pub team: Team,
+	pub user: Option<UserKey>,
+	/// When a session without a user is forgotten
+	pub expires: Option<Instant>,
+}
+
+/// Every player slot on the server, connected or not
+#[derive(Resource)]
+pub struct Sessions {
+	pub max_players: usize,
+	sessions: HashMap<u64, Session>,
+	users: HashMap<UserKey, u64>,
+	free_ids: BTreeSet<u32>,
+	next_client_id: u32,
+}
+
+impl Default for Sessions {
+	fn default() -> Self {
+		Sessions::new(DEFAULT_MAX_PLAYERS)
+	}
+}
+
+impl Sessions {
+	pub fn new(max_players: usize) -> Self {
+		Sessions {
+			max_players,
+			sessions: HashMap::new(),
+			users: HashMap::new(),
+			free_ids: BTreeSet::new(),
+			next_client_id: 1,
+		}
+	}
+
+	/// Whether `pending` more players would go over the limit
+	pub fn is_full(&self, pending: usize) -> bool {
+		self.sessions.len() + pending >= self.max_players
+	}
+
+	pub fn get(&self, session: u64) -> Option<&Session> {
+		self.sessions.get(&session)
+	}
+
+	pub fn of_user(&self, uid: &UserKey) -> Option<&Session> {
+		self.users.get(uid).and_then(|s| self.sessions.get(s))
+	}
+
+	pub fn entity_of(&self, uid: &UserKey) -> Option<Entity> {
+		self.of_user(uid).map(|s| s.ent)
+	}
+
+	/// Sessions with someone currently connected to them
+	pub fn connected(&self) -> impl Iterator<Item = (UserKey, &Session)> {
+		self.users.iter().map(|(uid, s)| (*uid, &self.sessions[s]))
+	}
+
+	/// Every session, including those waiting to be resumed
+	pub fn iter(&self) -> impl Iterator<Item = &Session> {
+		self.sessions.values()
+	}
+
+	/// Reserves a client id, preferring the lowest one freed up
+	pub fn alloc_client_id(&mut self) -> u32 {
+		if let Some(id) = self.free_ids.pop_first() {
+			return id;
+		}
+
+		let id = self.next_client_id;
+		self.next_client_id = id.wrapping_add(1);
+		id
+	}
+
+	/// Registers a new session for `uid`, returning its id
+	pub fn open(&mut self, uid: UserKey, client_id: u32, name: String, team: Team, ent: Entity) -> u64 {
+		// holding one is enough to resume as that player, so it mustn't be guessable
+		let id = loop {
+			let mut bytes = [0; 8];
+			getrandom::getrandom(&mut bytes).expect("no OS randomness for session ids");
+			let id = u64::from_ne_bytes(bytes);
+			if !self.sessions.contains_key(&id) {
+				break id;
+			}
+		};
+
+		self.sessions.insert(id, Session { client_id, ent, name, team, user: Some(uid), expires: None });
+		self.users.insert(uid, id);
+		id
+	}
+
+	/// Hands `session` over to `uid`, from whoever held it before
+	pub fn resume(&mut self, session: u64, uid: UserKey) -> Option<&Session> {
+		let s = self.sessions.get_mut(&session)?;
+		if let Some(prev) = s.user.replace(uid) {
+			self.users.remove(&prev);
+		}
+		s.expires = None;
+		self.users.insert(uid, session);
+
+		Some(s)
+	}
+
+	/// Detaches `uid` from its session, which lives on until `expires`
+	pub fn suspend(&mut self, uid: &UserKey, expires: Instant) -> Option<&Session> {
+		let session = self.users.remove(uid)?;
+		let s = self.sessions.get_mut(&session)?;
+		s.user = None;
+		s.expires = Some(expires);
+
+		Some(s)
+	}
+
+	/// Forgets every session that's gone unclaimed too long, freeing their ids
+	pub fn expire(&mut self, now: Instant) -> Vec<Session> {
+		let expired: Vec<_> = self.sessions.iter()
+			.filter(|(_, s)| s.expires.is_some_and(|t| now >= t))
+			.map(|(id, _)| *id)
+			.collect();
+
+		let expired: Vec<_> = expired.iter()
+			.filter_map(|id| self.sessions.remove(id))
+			.collect();
+		for s in &expired {
+			self.free_ids.insert(s.client_id);
+		}
+
+		expired
+	}
+}
+
+/// What other server systems get to know about a connected player
+pub struct PlayerInfo<'a> {
+	pub user: UserKey,
+	pub client_id: u32,
+	pub ent: Entity,
+	pub name: &'a str,
+	pub team: Team,
+	/// Average round trip time, in milliseconds
+	pub rtt_ms: Option<f32>,
+}
+
+/// Read-only view of connected players. Reads naia's server directly, so it
+/// can't be used alongside naia's `Server` param in the same system.
+#[derive(SystemParam)]
+pub struct Players<'w> {
+	sessions: Res<'w, Sessions>,
+	server: Res<'w, naia_server::Server<Entity>>,
+}
+
+impl Players<'_> {
+	pub fn iter(&self) -> impl Iterator<Item = PlayerInfo<'_>> {
+		self.sessions.connected().map(|(user, s)| PlayerInfo {
+			user,
+			client_id: s.client_id,
+			ent: s.ent,
+			name: &s.name,
+			team: s.team,
+			rtt_ms: self.server.rtt(&user),
+		})
+	}
+
+	pub fn get(&self, user: &UserKey) -> Option<PlayerInfo<'_>> {
+		self.iter().find(|p| p.user == *user)
+	}
+}
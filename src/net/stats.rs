@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use naia_bevy_client::events::MessageEvents;
+use naia_bevy_shared::{ReceiveEvents, Tick};
+use std::time::{Duration, Instant};
+use crate::tick_schedule::TickSchedule;
+use super::{
+	config::StateChannel,
+	input_buffer::InputStats,
+	msg,
+	peer::{tick_diff, TickState},
+	predict::Prediction,
+	session::Sessions,
+};
+
+// how long naia averages bandwidth over
+pub const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+// how often stats are refreshed and logged, on each side
+const CLIENT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+const SERVER_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps `NetStats` up to date, and logs it every second
+pub struct NetStatsPlugin;
+
+impl Plugin for NetStatsPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<NetStats>()
+			.add_systems(TickSchedule::Network, sys_count_states.in_set(ReceiveEvents))
+			.add_systems(TickSchedule::PostTicks, sys_update_stats);
+	}
+}
+
+/// How the connection to the server is holding up, as of the last refresh
+#[derive(Clone, Copy, Debug, Default, Resource)]
+pub struct NetStats {
+	pub rtt_ms: f32,
+	pub jitter_ms: f32,
+	/// Fraction of the server's state updates that never arrived
+	pub loss: f32,
+	pub bytes_in_per_sec: f32,
+	pub bytes_out_per_sec: f32,
+	pub ticks_pending: usize,
+	pub corrections_per_sec: f32,
+}
+
+/// Tallies of what arrived since the last refresh
+#[derive(Default)]
+struct StateCount {
+	last_tick: Option<Tick>,
+	received: u32,
+	missed: u32,
+}
+
+/// The server sends our state every tick, so gaps between them are lost packets
+fn sys_count_states(
+	mut event_sets: EventReader<MessageEvents>,
+	mut count: Local<StateCount>,
+	mut stats: ResMut<NetStats>,
+	mut last_refresh: Local<Option<Instant>>,
+) {
+	for events in event_sets.read() {
+		for state in events.read::<StateChannel, msg::PlayerState>() {
+			if let Some(last) = count.last_tick {
+				let gap = tick_diff(last, state.tick);
+				if gap <= 0 {
+					continue;
+				}
+				count.missed += gap as u32 - 1;
+			}
+			count.last_tick = Some(state.tick);
+			count.received += 1;
+		}
+	}
+
+	let since = last_refresh.get_or_insert_with(Instant::now);
+	if since.elapsed() >= CLIENT_STATS_INTERVAL {
+		*since = Instant::now();
+		let total = count.received + count.missed;
+		stats.loss = if total > 0 { count.missed as f32 / total as f32 } else { 0.0 };
+		count.received = 0;
+		count.missed = 0;
+	}
+}
+
+fn sys_update_stats(
+	mut client: ResMut<naia_client::Client<Entity>>,
+	state: Res<TickState>,
+	prediction: Res<Prediction>,
+	mut stats: ResMut<NetStats>,
+	mut last: Local<Option<(Instant, u32)>>,
+) {
+	let now = Instant::now();
+	let (since, corrections) = *last.get_or_insert((now, prediction.corrections));
+	let elapsed = now - since;
+	if elapsed < CLIENT_STATS_INTERVAL {
+		return;
+	}
+	*last = Some((now, prediction.corrections));
+
+	if client.is_connected() {
+		stats.rtt_ms = client.rtt();
+		stats.jitter_ms = client.jitter();
+	}
+	stats.bytes_in_per_sec = kbps_to_bytes(client.incoming_bandwidth());
+	stats.bytes_out_per_sec = kbps_to_bytes(client.outgoing_bandwidth());
+	stats.ticks_pending = state.ticks_pending;
+	stats.corrections_per_sec = prediction.corrections.wrapping_sub(corrections) as f32
+		/ elapsed.as_secs_f32();
+
+	info!(
+		"rtt:{:.1}ms, jitter:{:.1}ms, loss:{:.1}%, in:{:.0}B/s, out:{:.0}B/s, pending:{}, corrections:{:.1}/s",
+		stats.rtt_ms,
+		stats.jitter_ms,
+		stats.loss * 100.0,
+		stats.bytes_in_per_sec,
+		stats.bytes_out_per_sec,
+		stats.ticks_pending,
+		stats.corrections_per_sec,
+	);
+}
+
+fn kbps_to_bytes(kbps: f32) -> f32 {
+	kbps * 1000.0 / 8.0
+}
+
+/// Logs overall traffic and input health, then how each player's connection is doing
+pub fn sys_log_server_stats(
+	mut server: ResMut<naia_server::Server<Entity>>,
+	sessions: Res<Sessions>,
+	input: Res<InputStats>,
+	mut last: Local<Option<(Instant, InputStats)>>,
+) {
+	let now = Instant::now();
+	let (since, prev) = *last.get_or_insert((now, *input));
+	if now - since < SERVER_STATS_INTERVAL {
+		return;
+	}
+	*last = Some((now, *input));
+
+	info!(
+		"players:{}, in:{:.0}B/s, out:{:.0}B/s, input recovered:{}, late:{}, missing:{}",
+		sessions.connected().count(),
+		kbps_to_bytes(server.incoming_bandwidth_total()),
+		kbps_to_bytes(server.outgoing_bandwidth_total()),
+		input.recovered - prev.recovered,
+		input.late - prev.late,
+		input.missing - prev.missing,
+	);
+
+	for (uid, session) in sessions.connected() {
+		let addr = server.user(&uid).address();
+		info!(
+			"client:{}, rtt:{:.1}ms, jitter:{:.1}ms, in:{:.0}B/s, out:{:.0}B/s",
+			session.client_id,
+			server.rtt(&uid).unwrap_or(0.0),
+			server.jitter(&uid).unwrap_or(0.0),
+			kbps_to_bytes(server.incoming_bandwidth_from_client(&addr)),
+			kbps_to_bytes(server.outgoing_bandwidth_to_client(&addr)),
+		);
+	}
+}
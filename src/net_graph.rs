@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use crate::net::stats::NetStats;
+
+/// Overlays the latest `NetStats` in a corner of the screen, while enabled
+pub struct NetGraphPlugin;
+
+impl Plugin for NetGraphPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.init_resource::<NetGraph>()
+			.add_systems(Startup, spawn_net_graph)
+			.add_systems(Update, (
+				sys_net_graph_toggle.run_if(resource_changed::<NetGraph>()),
+				sys_net_graph_text.run_if(net_graph_enabled),
+			));
+	}
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct NetGraph {
+	pub enabled: bool,
+}
+
+pub fn net_graph_enabled(graph: Res<NetGraph>) -> bool {
+	graph.enabled
+}
+
+#[derive(Component)]
+struct NetGraphText;
+
+fn spawn_net_graph(mut cmds: Commands) {
+	let style = TextStyle {
+		font_size: 16.0,
+		color: Color::WHITE,
+		..default()
+	};
+
+	cmds.spawn((
+		NetGraphText,
+		TextBundle::from_section("", style)
+			.with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.5))
+			.with_style(Style {
+				position_type: PositionType::Absolute,
+				top: Val::Px(8.0),
+				left: Val::Px(8.0),
+				padding: UiRect::all(Val::Px(4.0)),
+				..default()
+			}),
+		Visibility::Hidden,
+	));
+}
+
+fn sys_net_graph_toggle(
+	graph: Res<NetGraph>,
+	mut q_text: Query<&mut Visibility, With<NetGraphText>>,
+) {
+	for mut vis in &mut q_text {
+		*vis = if graph.enabled { Visibility::Inherited } else { Visibility::Hidden };
+	}
+}
+
+fn sys_net_graph_text(
+	stats: Res<NetStats>,
+	mut q_text: Query<&mut Text, With<NetGraphText>>,
+) {
+	if !stats.is_changed() {
+		return;
+	}
+
+	for mut text in &mut q_text {
+		text.sections[0].value = format!(
+			"rtt: {:.1} ms\njitter: {:.1} ms\nloss: {:.1}%\nin: {:.1} KB/s\nout: {:.1} KB/s\npending: {} ticks\ncorrections: {:.1}/s",
+			stats.rtt_ms,
+			stats.jitter_ms,
+			stats.loss * 100.0,
+			stats.bytes_in_per_sec / 1000.0,
+			stats.bytes_out_per_sec / 1000.0,
+			stats.ticks_pending,
+			stats.corrections_per_sec,
+		);
+	}
+}
@@ -1,5 +1,6 @@
 use bevy::{
-	ecs::component::Component,
+	ecs::{component::Component, reflect::ReflectComponent},
+	math::Vec2,
 	reflect::Reflect,
 };
 use crate::time::Accumulator;
@@ -8,3 +9,61 @@ use crate::time::Accumulator;
 pub struct Player {
 	pub shot_acc: Option<Accumulator>,
 }
+
+/// Which client a player belongs to; unlike their `Entity`, the same on every
+/// machine. 0 for our own player until the server assigns it.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Reflect)]
+#[reflect(Component)]
+pub struct PlayerId(pub u32);
+
+/// What a player wants to do this tick, regardless of where the input came from
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct Intent {
+	pub dir: Vec2,
+	pub face_turns: f32,
+	pub primary: bool,
+}
+
+/// Marks the player controlled by this client
+#[derive(Component, Default, Reflect)]
+pub struct LocalPlayer;
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub enum Team {
+	Blue,
+	Red,
+	Green,
+	#[default]
+	Purple,
+}
+
+impl Team {
+	pub const ALL: [Team; 4] = [Team::Blue, Team::Red, Team::Green, Team::Purple];
+
+	pub fn from_index(i: u8) -> Self {
+		Self::ALL[i as usize % Self::ALL.len()]
+	}
+
+	pub fn index(&self) -> u8 {
+		*self as u8
+	}
+
+	pub fn player_skin(&self) -> &'static str {
+		match self {
+			Team::Blue => "player_blue",
+			Team::Red => "player_red",
+			Team::Green => "player_green",
+			Team::Purple => "player_purple",
+		}
+	}
+
+	pub fn shot_skin(&self) -> &'static str {
+		match self {
+			Team::Blue => "shot_blue",
+			Team::Red => "shot_red",
+			Team::Green => "shot_green",
+			Team::Purple => "shot_purple",
+		}
+	}
+}
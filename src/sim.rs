@@ -0,0 +1,606 @@
+use bevy::{
+	ecs::{
+		query::{ReadOnlyWorldQuery, WorldQuery},
+		schedule::SystemConfigs,
+	},
+	prelude::*,
+};
+use naia_bevy_shared::Tick;
+use parry2d::partitioning::Qbvh;
+use std::collections::HashMap;
+use crate::{
+	collide::{Collidable, contact, EntityHandle, Excluding, PosedShapes, toi, toi_hit, ToiResult},
+	layer::Layer,
+	movement::{Facing, Position, Velocity},
+	net::peer::TickState,
+	player::{Intent, Player, PlayerId, Team},
+	tick_schedule::TickConfig,
+	time::Accumulator,
+};
+
+/// Game state shared by the client and the server. Nothing in here may depend
+/// on rendering, audio or assets, since the server runs without them.
+pub struct SimPlugin;
+
+impl Plugin for SimPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.register_type::<Accumulator>()
+			.register_type::<Facing>()
+			.register_type::<Intent>()
+			.register_type::<Player>()
+			.register_type::<PlayerId>()
+			.register_type::<Position>()
+			.register_type::<Proxy>()
+			.register_type::<Shot>()
+			.register_type::<Skin>()
+			.register_type::<Team>()
+			.register_type::<Velocity>()
+			.add_event::<ShotHit>()
+			.init_resource::<Separation>()
+			.init_resource::<Targets>()
+			.insert_resource(Statics(Qbvh::new()))
+			.add_systems(Startup, spawn_statics)
+			.add_systems(PostStartup, sys_index_statics);
+	}
+}
+
+/// Systems advancing the simulation by one tick, once `Intent`s are up to date
+pub fn systems_tick() -> SystemConfigs {
+	(
+		sys_apply_intent,
+		sys_spawn_shot,
+		sys_index_targets,
+		sys_move_shots,
+		sys_move_player,
+	).chain()
+}
+
+/// Name of the texture atlas used to draw an entity, for whoever can draw it
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct Skin(pub String);
+
+impl From<&str> for Skin {
+	fn from(name: &str) -> Self {
+		Skin(name.to_string())
+	}
+}
+
+#[derive(Resource)]
+pub struct Statics(pub Qbvh<EntityHandle>);
+
+#[derive(Component, Default, Reflect)]
+pub struct Static;
+
+/// Fraction of any overlap between players undone each tick, by pushing them
+/// apart; 0 leaves them overlapped, e.g. after spawning on top of each other
+#[derive(Clone, Copy, Debug, Resource)]
+pub struct Separation(pub f32);
+
+impl Default for Separation {
+	fn default() -> Self {
+		Separation(0.25)
+	}
+}
+
+/// Players as shots should find them this tick
+#[derive(Default, Resource)]
+pub struct Targets {
+	everyone: PosedShapes,
+	views: HashMap<Entity, PosedShapes>,
+}
+
+impl Targets {
+	/// Has `shooter`'s shots look for targets among `shapes` instead
+	pub fn set_view(&mut self, shooter: Entity, shapes: PosedShapes) {
+		self.views.insert(shooter, shapes);
+	}
+
+	fn view(&self, shooter: Option<Entity>) -> &PosedShapes {
+		shooter.and_then(|s| self.views.get(&s)).unwrap_or(&self.everyone)
+	}
+}
+
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Shot {
+	pub bounces: u8,
+	pub shooter: Option<Entity>,
+	/// Bounces off players like it does walls, rather than stopping at the first
+	pub ricochets: bool,
+}
+
+/// A shot ran into a player
+#[derive(Clone, Copy, Debug, Event)]
+pub struct ShotHit {
+	pub shot: Entity,
+	pub target: Entity,
+	/// Where they first touched
+	pub point: Vec2,
+	/// Out of the target, toward the shot
+	pub normal: Vec2,
+	pub tick: Tick,
+}
+
+/// Mirrors an entity simulated elsewhere; its lifetime isn't ours to manage
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Proxy;
+
+pub const PLAYER_RADIUS: f32 = 96.0;
+pub const PLAYER_SPEED: f32 = 900.0;
+pub const SHOT_RADIUS: f32 = 26.0;
+pub const SHOT_SPEED: f32 = 2700.0;
+
+pub fn player_bundle(id: PlayerId, name: &str, team: Team, pos: Position) -> impl Bundle {
+	(
+		Player::default(),
+		id,
+		Intent::default(),
+		Name::new(name.to_string()),
+		team,
+		Skin::from(team.player_skin()),
+		TransformBundle::from_transform(Transform::from_xyz(pos.p.x, pos.p.y, Layer::PLAYER)),
+		Collidable::circle(PLAYER_RADIUS),
+		pos,
+		Velocity::ZERO,
+		Facing::default(),
+	)
+}
+
+pub fn shot_bundle(team: Team, shooter: Option<Entity>, pos: Vec2, vel: Vec2) -> impl Bundle {
+	(
+		Shot{ bounces: 3, shooter, ricochets: false },
+		Name::new("Shot"),
+		team,
+		Skin::from(team.shot_skin()),
+		TransformBundle::from_transform(Transform::from_xyz(pos.x, pos.y, Layer::SHOT)),
+		Collidable::circle(SHOT_RADIUS),
+		Position::from(pos),
+		Velocity::from(vel),
+	)
+}
+
+/// Half the size of the walled-in map; nothing in play ever strays outside it
+pub const MAP_EXTENT: Vec2 = Vec2::new(1280.0, 1920.0);
+
+pub fn spawn_statics(mut cmds: Commands) {
+	{
+		// sizes are before rotation, as the textures are laid out
+		let mut mk_wall = |name, skin, x, y, w, h, turns| {
+			let pos = Position::new(x, y).with_rotation(turns);
+			cmds.spawn((
+				Static,
+				Name::new(name),
+				Skin::from(skin),
+				TransformBundle::from_transform(Transform
+					::from_rotation(pos.to_quat())
+					.with_translation(Vec3::new(x, y, Layer::STATIC))),
+				Collidable::aa_rect(w, h),
+				pos,
+			));
+		};
+
+		mk_wall("Wall - Left", "wall_out_left", -1184.0, 0.0, 96.0, 3840.0, 0.0);
+		mk_wall("Wall - Right", "wall_out_right", 1184.0, 0.0, 96.0, 3840.0, 0.0);
+		mk_wall("Wall - Top", "wall_out_top", 0.0, 1824.0, 96.0, 2560.0, 0.25);
+		mk_wall("Wall - Bottom", "wall_out_bottom", 0.0, -1824.0, 96.0, 2560.0, 0.25);
+		mk_wall("Wall - Horizontal", "wall_in_horizontal", -196.0, -1149.5, 299.0, 1066.0, 0.25);
+		mk_wall("Wall - Verticle", "wall_in_verticle", 702.0, 288.5, 296.0, 2465.0, 0.0);
+	}
+
+	{
+		let mut mk_bush = |x, y| {
+			cmds.spawn((
+				Static,
+				Name::new(format!("Bush ({}, {})", x, y)),
+				Skin::from("bush"),
+				TransformBundle::from_transform(Transform::from_xyz(x, y, Layer::STATIC)),
+				Collidable::circle(128.0),
+				Position::new(x, y),
+			));
+		};
+
+		mk_bush(-128.0, 1228.0);
+		mk_bush(128.0, 1100.0);
+		mk_bush(-512.0, 64.0);
+		mk_bush(192.0, -512.0);
+		mk_bush(64.0, -640.0);
+		mk_bush(760.0, -1400.0);
+	}
+}
+
+pub fn sys_index_statics(
+	mut statics: ResMut<Statics>,
+	q_walls: Query<(Entity, &Collidable, &Position), With<Static>>
+) {
+	let statics = &mut statics.0;
+	let shapes = q_walls.iter()
+		.map(|(ent, col, pos)| (EntityHandle::from(ent), col.shape.compute_aabb(&pos.to_iso())));
+	statics.clear_and_rebuild(shapes, 0.0);
+}
+
+pub fn reflect(v: Vec2, norm: Vec2) -> Vec2 {
+	v - 2.0 * v.dot(norm) * norm
+}
+
+pub fn slide(v: Vec2, norm: Vec2) -> Vec2 {
+	v - v.dot(norm) * norm
+}
+
+pub fn sys_apply_intent(
+	mut q_player: Query<(&Intent, &mut Velocity, &mut Facing, &mut Player)>,
+) {
+	for (intent, mut vel, mut facing, mut player) in &mut q_player {
+
+		// shooting
+
+		if intent.primary && player.shot_acc.is_none() {
+			player.shot_acc = Some(Accumulator::ready_from_millis(100));
+		} else if !intent.primary && player.shot_acc.is_some() {
+			player.shot_acc = None;
+		}
+
+		// movement
+
+		vel.v = PLAYER_SPEED * intent.dir;
+		facing.turns = intent.face_turns;
+	}
+}
+
+/// Proxies don't shoot; their shots are mirrored from wherever they're simulated
+pub fn sys_spawn_shot(
+	mut cmds: Commands,
+	tick: Res<TickConfig>,
+	mut q_player: Query<(Entity, &Position, &Facing, &Team, &mut Player), Without<Proxy>>
+) {
+	let step_ns = tick.step.as_nanos();
+
+	for (ent, player_p, facing, team, mut player) in &mut q_player {
+		let dir = facing.dir();
+		let pos = player_p.p + dir * (PLAYER_RADIUS + SHOT_RADIUS);
+
+		if let Some(acc) = &mut player.shot_acc {
+			for _ in acc.advance(step_ns as u64) {
+				cmds.spawn(shot_bundle(*team, Some(ent), pos, dir * SHOT_SPEED));
+			}
+		}
+	}
+}
+
+/// Poses every player for shots to test against, leaving out each shooter from
+/// their own view
+pub fn sys_index_targets(
+	mut targets: ResMut<Targets>,
+	q_players: Query<(Entity, &Collidable, &Position), With<Player>>,
+) {
+	let poses: Vec<_> = q_players.iter()
+		.map(|(ent, col, pos)| (ent, col.shape.clone(), pos.clone()))
+		.collect();
+
+	targets.views.clear();
+	for (shooter, _, _) in &poses {
+		let others = poses.iter().filter(|(ent, _, _)| ent != shooter).cloned();
+		targets.views.insert(*shooter, PosedShapes::new(others));
+	}
+	targets.everyone = PosedShapes::new(poses);
+}
+
+/// When a sweep first touches something; contacts already have
+fn toi_sec(res: &ToiResult) -> f32 {
+	match res {
+		ToiResult::Contact(_) => 0.0,
+		ToiResult::Miss => f32::INFINITY,
+		ToiResult::Toi(toi) => toi.toi_sec,
+	}
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn sys_move_shots(
+	mut cmds: Commands,
+	statics: Res<Statics>,
+	targets: Res<Targets>,
+	tick: Res<TickConfig>,
+	state: Res<TickState>,
+	mut hits: EventWriter<ShotHit>,
+	mut q_shots: Query<(Entity, &Collidable, &mut Position, &mut Velocity, &mut Shot), Without<Proxy>>,
+	q_statics: Query<(Entity, &Collidable, &Position), (With<Static>, Without<Shot>)>,
+) {
+	let statics = &statics.0;
+	let step_secs = tick.step.as_secs_f32();
+
+	for (ent, col, mut pos, mut vel, mut shot) in &mut q_shots {
+
+		//info!("    ----");
+
+		let mut max_toi = step_secs;
+		let mut limit = 8;
+		while max_toi > 0.0 && limit > 0 {
+			limit -= 1;
+
+			//info!("pos: {:?}; v: {:?}; max_toi: {}", pos.p, vel.v, max_toi);
+
+			let margin:f32 = 1024.0 * f32::EPSILON;
+			let wall = toi(&q_statics, statics, col, &pos, &vel, max_toi);
+
+			// players only count if they're actually struck, and before any wall is
+			let player = toi_hit(targets.view(shot.shooter), col, &pos, &vel, max_toi)
+				.filter(|(_, res)| match res {
+					ToiResult::Contact(c) => c.dist > 0.0,
+					_ => true,
+				})
+				.filter(|(_, res)| toi_sec(res) < toi_sec(&wall));
+			if let Some((target, res)) = player {
+				let (point, normal, toi_sec, depth) = match res {
+					ToiResult::Contact(c) => (c.pos, c.norm, 0.0, c.dist),
+					ToiResult::Toi(t) => (t.pos, t.norm, t.toi_sec, 0.0),
+					ToiResult::Miss => unreachable!(),
+				};
+				hits.send(ShotHit { shot: ent, target, point, normal, tick: state.cur_tick });
+
+				if !shot.ricochets || shot.bounces == 0 {
+					cmds.entity(ent)
+						.despawn_recursive();
+					break;
+				}
+
+				shot.bounces -= 1;
+
+				max_toi -= toi_sec;
+				pos.p += vel.v * toi_sec + normal * (depth + margin);
+				vel.v = reflect(vel.v, normal);
+				continue;
+			}
+
+			match wall {
+				ToiResult::Miss => {
+					pos.p += vel.v * max_toi;
+					break;
+				},
+				ToiResult::Contact(contact) => {
+					//info!("contact: {:?}", contact);
+
+					pos.p += contact.norm * (contact.dist + margin);
+				},
+				ToiResult::Toi(toi) => {
+					//info!("toi: {:?}", toi);
+
+					if shot.bounces == 0 {
+						cmds.entity(ent)
+							.despawn_recursive();
+						break;
+					}
+
+					shot.bounces -= 1;
+
+					max_toi -= toi.toi_sec;
+					pos.p += vel.v * toi.toi_sec + toi.norm * margin;
+					vel.v = reflect(vel.v, toi.norm);
+				},
+			}
+		}
+	}
+}
+
+/// Moves everyone against where the others were at the start of the tick, then
+/// pushes apart whoever ends up overlapping, so the order players are visited in
+/// never matters, and prediction can replay just the one
+#[allow(clippy::type_complexity)]
+pub fn sys_move_player(
+	statics: Res<Statics>,
+	tick: Res<TickConfig>,
+	separation: Res<Separation>,
+	mut q_player: Query<(Entity, &PlayerId, &Collidable, &mut Position, &Velocity), With<Player>>,
+	q_statics: Query<(Entity, &Collidable, &Position), (With<Static>, Without<Player>)>,
+) {
+	let step_secs = tick.step.as_secs_f32();
+
+	// by id, rather than however the query happens to be laid out here
+	let mut start: Vec<_> = q_player.iter()
+		.map(|(ent, id, col, pos, _)| (*id, ent, col.shape.clone(), pos.clone()))
+		.collect();
+	start.sort_by_key(|(id, ..)| *id);
+	let players = PosedShapes::new(start.into_iter().map(|(_, ent, shape, pos)| (ent, shape, pos)));
+	for (ent, _, col, mut pos, vel) in &mut q_player {
+		move_player(&q_statics, &statics, &players.excluding(ent), col, &mut pos, vel, step_secs);
+	}
+
+	if separation.0 <= 0.0 {
+		return;
+	}
+
+	let mut moved: Vec<_> = q_player.iter()
+		.map(|(_, id, col, pos, _)| (*id, col.clone(), pos.clone()))
+		.collect();
+	moved.sort_by_key(|(id, ..)| *id);
+	for (_, id, col, mut pos, _) in &mut q_player {
+		let push = push_apart(*id, col, &pos, &moved);
+		pos.p += separation.0 * push;
+	}
+}
+
+/// How far player `id` would have to move to stop overlapping any of `others`,
+/// if they each moved as far the other way. `others` must be sorted by id, so
+/// the pushes add up the same wherever this runs.
+pub fn push_apart(
+	id: PlayerId,
+	col: &Collidable,
+	pos: &Position,
+	others: &[(PlayerId, Collidable, Position)],
+) -> Vec2 {
+	let mut push = Vec2::ZERO;
+	for (other, other_col, other_pos) in others {
+		if *other == id {
+			continue;
+		}
+
+		let c = unwrap!(contact(col, pos, other_col, other_pos), { continue; });
+		if c.dist <= 0.0 {
+			continue;
+		}
+
+		// right on top of each other, any way out will do, so long as it's
+		// opposite the other's, and the same wherever this runs
+		let norm = if pos.p.distance_squared(other_pos.p) > f32::EPSILON {
+			c.norm
+		} else if id < *other {
+			Vec2::NEG_X
+		} else {
+			Vec2::X
+		};
+		push += norm * c.dist / 2.0;
+	}
+	push
+}
+
+/// Advances a single player by `step_secs`, sliding along any statics or other
+/// players in the way. Players already overlapping are left to `push_apart`.
+pub fn move_player<Q: WorldQuery, F: ReadOnlyWorldQuery>(
+	q_statics: &Query<Q, F>,
+	statics: &Statics,
+	players: &Excluding,
+	col: &Collidable,
+	pos: &mut Position,
+	vel: &Velocity,
+	step_secs: f32,
+) {
+	let statics = &statics.0;
+
+	if vel.v == Vec2::ZERO {
+		return;
+	}
+
+	//info!("    ----");
+
+	let mut max_toi = step_secs;
+	let mut v = vel.clone();
+	let mut overlapping = false;
+	let mut limit = 8;
+	while max_toi > 0.0 && limit > 0 {
+		limit -= 1;
+
+		//info!("pos: {:?}; v: {:?}; max_toi: {}", pos.p, v.v, max_toi);
+
+		let margin:f32 = 8192.0 * f32::EPSILON;
+		let wall = toi(q_statics, statics, col, pos, &v, max_toi);
+
+		let player = if overlapping {
+			None
+		} else {
+			toi_hit(players, col, pos, &v, max_toi)
+				.filter(|(_, res)| toi_sec(res) < toi_sec(&wall))
+		};
+		match player {
+			Some((_, ToiResult::Contact(contact))) => {
+				// don't dig in any deeper, but don't get stuck either
+				if v.v.dot(contact.norm) < 0.0 {
+					v.v = slide(v.v, contact.norm);
+				}
+				overlapping = true;
+				continue;
+			},
+			Some((_, ToiResult::Toi(toi))) => {
+				max_toi -= toi.toi_sec;
+				pos.p += v.v * toi.toi_sec + toi.norm * margin;
+				v.v = slide(v.v, toi.norm);
+				continue;
+			},
+			_ => {},
+		}
+
+		match wall {
+			ToiResult::Miss => {
+				pos.p += v.v * max_toi;
+				break;
+			},
+			ToiResult::Contact(contact) => {
+				//info!("contact: {:?}", contact);
+
+				pos.p += contact.norm * (contact.dist + margin);
+				v.v = slide(v.v, contact.norm);
+			},
+			ToiResult::Toi(toi) => {
+				//info!("toi: {:?}", toi);
+
+				max_toi -= toi.toi_sec;
+				pos.p += v.v * toi.toi_sec + toi.norm * margin;
+				v.v = slide(v.v, toi.norm);
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use super::*;
+
+	// runs a tick for players spawned in the given order, as both ends would
+	// spawn them in whatever order they happened to hear about them
+	fn tick(players: &[(PlayerId, Vec2)]) -> HashMap<PlayerId, Vec2> {
+		let mut app = App::new();
+		app
+			.insert_resource(Statics(Qbvh::new()))
+			.insert_resource(TickConfig {
+				budget: Duration::from_millis(100),
+				interval: Duration::from_millis(16),
+				step: Duration::from_millis(16),
+			})
+			.init_resource::<Separation>()
+			.add_systems(Update, sys_move_player);
+		for (id, p) in players {
+			app.world.spawn(player_bundle(*id, "Player", Team::default(), Position::from(*p)));
+		}
+		app.update();
+
+		let mut q = app.world.query::<(&PlayerId, &Position)>();
+		q.iter(&app.world).map(|(id, pos)| (*id, pos.p)).collect()
+	}
+
+	#[test]
+	fn stacked_players_separate_the_same_everywhere() {
+		let (a, b) = (PlayerId(1), PlayerId(2));
+		let server = tick(&[(a, Vec2::ZERO), (b, Vec2::ZERO)]);
+		let client = tick(&[(b, Vec2::ZERO), (a, Vec2::ZERO)]);
+
+		assert_eq!(server, client);
+		assert!(server[&a].x < 0.0 && server[&b].x > 0.0, "{:?}", server);
+	}
+
+	#[test]
+	fn overlapping_players_separate_the_same_everywhere() {
+		let (a, b) = (PlayerId(1), PlayerId(2));
+		let (pa, pb) = (Vec2::new(3.0, 4.0), Vec2::new(-5.0, 1.0));
+		let server = tick(&[(a, pa), (b, pb)]);
+		let client = tick(&[(b, pb), (a, pa)]);
+
+		assert_eq!(server, client);
+		assert!(server[&a].distance(server[&b]) > pa.distance(pb), "{:?}", server);
+	}
+
+	#[test]
+	fn crowded_players_separate_the_same_in_any_order() {
+		// enough of them that each push sums several, which float rounding
+		// only agrees on when it's done in the same order
+		let players = [
+			(PlayerId(1), Vec2::new(0.3, 0.7)),
+			(PlayerId(2), Vec2::new(-5.1, 1.9)),
+			(PlayerId(3), Vec2::new(4.3, -6.7)),
+			(PlayerId(4), Vec2::new(-2.9, -3.3)),
+		];
+		let mut results = Vec::new();
+		for a in 0..4 {
+			for b in (0..4).filter(|&b| b != a) {
+				for c in (0..4).filter(|&c| c != a && c != b) {
+					let d = 6 - a - b - c;
+					results.push(tick(&[a, b, c, d].map(|i| players[i])));
+				}
+			}
+		}
+
+		for res in &results[1..] {
+			assert_eq!(*res, results[0]);
+		}
+	}
+}
@@ -6,7 +6,10 @@ use std::marker::Copy;
 #[derive(Clone, Copy, Resource)]
 pub struct TickConfig {
 	pub budget: Duration,
+	/// Real time between ticks; may drift from `step` to keep pace with a remote clock
 	pub interval: Duration,
+	/// Simulated time per tick; must agree everywhere the simulation runs
+	pub step: Duration,
 }
 
 #[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
@@ -36,7 +36,7 @@ impl Accumulator {
 		self.acc_ns += ns;
 
 		let n = self.acc_ns / self.interval_ns;
-		self.acc_ns = self.acc_ns % self.interval_ns;
+		self.acc_ns %= self.interval_ns;
 
 		0..n
 	}